@@ -0,0 +1,259 @@
+//! Request/reply exchanges between actors, see [`Pid::call`]
+
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, HashSet},
+    error, fmt,
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::{
+    monitor::demonitor_flush,
+    receive::{downcast_if, receive_timeout},
+    ActorId, Down, Error, ExitReason, LocalChannel, Message, Pid, ReceiveResult, ReceivedMessage,
+    Ref, MY_CHANNEL,
+};
+
+lazy_static! {
+    /// The tags of the instances of generic messages, by name and tag of the
+    /// type parameter
+    static ref TAGS: Mutex<HashMap<(&'static str, &'static str), &'static str>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Returns the tag of `name<param>`, where `param` is the tag of the type
+/// parameter
+pub(crate) fn generic_tag(name: &'static str, param: &'static str) -> &'static str {
+    TAGS.lock()
+        .unwrap()
+        .entry((name, param))
+        .or_insert_with(|| Box::leak(format!("{}<{}>", name, param).into_boxed_str()))
+}
+
+/// The actor waiting for the answer to a [`Call`]
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Caller {
+    pid: Pid,
+    reference: Ref,
+}
+
+impl Caller {
+    /// Returns the actor that made the call
+    pub fn pid(&self) -> &Pid {
+        &self.pid
+    }
+
+    /// Sends `response` as the answer to the call
+    ///
+    /// The response is dropped if the caller is no longer waiting for it,
+    /// eg. because the call timed out.
+    pub async fn reply<Resp: Message>(self, response: Resp) -> Result<(), Error> {
+        let mut pid = self.pid;
+        let reply = Reply {
+            reference: self.reference,
+            response,
+        };
+        pid.send(Box::new(reply)).await
+    }
+}
+
+/// A request made with [`Pid::call`], to be answered with [`Caller::reply`]
+#[derive(Deserialize, Serialize)]
+pub struct Call<Req> {
+    pub from:    Caller,
+    pub request: Req,
+}
+
+impl<Req: Message> Message for Call<Req> {
+    fn tag() -> &'static str {
+        generic_tag("erlust::Call", Req::tag())
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Reply<Resp> {
+    reference: Ref,
+    response:  Resp,
+}
+
+impl<Resp: Message> Message for Reply<Resp> {
+    fn tag() -> &'static str {
+        generic_tag("erlust::Reply", Resp::tag())
+    }
+}
+
+/// Returns the reference of `reply`, that must be a `Reply<Resp>`
+fn reply_ref<Resp: Message>(reply: &dyn Any) -> Ref {
+    reply.downcast_ref::<Reply<Resp>>().unwrap().reference
+}
+
+/// The part of a [`Reply`] that does not depend on the response type
+#[derive(Deserialize)]
+struct ReplyHeader {
+    reference: Ref,
+}
+
+/// The calls made by an actor
+#[derive(Default)]
+pub(crate) struct Calls {
+    /// The calls still waiting for their reply
+    pending: HashSet<Ref>,
+
+    /// Reads the reference of local replies, by [`TypeId`] of the reply, for
+    /// all the reply types the actor ever waited for
+    readers: HashMap<TypeId, fn(&dyn Any) -> Ref>,
+}
+
+/// A call waiting for its reply, that stops waiting when dropped
+struct Pending {
+    actor_id:  ActorId,
+    reference: Ref,
+}
+
+impl Pending {
+    fn new<Resp: Message>(reference: Ref) -> Pending {
+        crate::local_channel::with_my_channel(|chan| {
+            chan.calls.pending.insert(reference);
+            chan.calls
+                .readers
+                .insert(TypeId::of::<Reply<Resp>>(), reply_ref::<Resp>);
+            Pending {
+                actor_id: chan.actor_id,
+                reference,
+            }
+        })
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        MY_CHANNEL.with(|c| {
+            // If the channel is not there, then the whole actor is being
+            // dropped, and so are its calls
+            let mut cell = match c.try_borrow_mut() {
+                Ok(cell) => cell,
+                Err(_) => return,
+            };
+            if let Some(chan) = cell.as_mut().filter(|c| c.actor_id == self.actor_id) {
+                chan.calls.pending.remove(&self.reference);
+            }
+        });
+    }
+}
+
+/// Error returned by [`Pid::call`]
+#[derive(Debug)]
+pub enum CallError {
+    /// No answer was received in time
+    Timeout,
+
+    /// The called actor terminated, or was unreachable, for this reason
+    Down(ExitReason),
+
+    /// The request could not be sent
+    Send(Error),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallError::Timeout => f.write_str("call timed out"),
+            CallError::Down(reason) => write!(f, "called actor is down: {:?}", reason),
+            CallError::Send(e) => write!(f, "failed sending the call: {}", e),
+        }
+    }
+}
+
+impl error::Error for CallError {}
+
+/// Checks whether `msg` is the reply to a call that is no longer waiting for
+/// it, eg. because it timed out, in which case `msg` should be dropped
+pub(crate) fn is_late_reply(chan: &LocalChannel, msg: &ReceivedMessage) -> bool {
+    // Only actors that made calls can receive replies
+    if chan.calls.readers.is_empty() {
+        return false;
+    }
+    let reference = match msg {
+        ReceivedMessage::Local((_, msg)) => {
+            let msg = msg.as_any();
+            match chan.calls.readers.get(&msg.type_id()) {
+                Some(read) => read(msg),
+                None => return false,
+            }
+        }
+        ReceivedMessage::Remote((from, msg)) if msg.tag.starts_with("erlust::Reply<") => {
+            let mut header = None;
+            let _ = from.__theater_assert_remote().decode(&msg.msg, &mut |d| {
+                header = Some(erased_serde::deserialize::<ReplyHeader>(d)?);
+                Ok(())
+            });
+            match header {
+                Some(header) => header.reference,
+                None => return false,
+            }
+        }
+        ReceivedMessage::Remote(_) => return false,
+    };
+    !chan.calls.pending.contains(&reference)
+}
+
+/// See [`Pid::call`]
+pub(crate) async fn call<Req, Resp>(
+    pid: &Pid,
+    request: Req,
+    timeout: Duration,
+) -> Result<Resp, CallError>
+where
+    Req: Message,
+    Resp: Message,
+{
+    // The monitor reference is unique, and thus identifies the reply too
+    let monitor_ref = pid.monitor().await;
+    let _pending = Pending::new::<Resp>(monitor_ref);
+    let call = Call {
+        from: Caller {
+            pid: Pid::me(),
+            reference: monitor_ref,
+        },
+        request,
+    };
+    if let Err(e) = pid.clone().send(Box::new(call)).await {
+        demonitor_flush(pid, monitor_ref).await;
+        return Err(CallError::Send(e));
+    }
+
+    let res = receive_timeout(
+        async move |msg: &mut Option<ReceivedMessage>| {
+            let is_reply = |reply: &Reply<Resp>| reply.reference == monitor_ref;
+            let m = match downcast_if(msg.take().unwrap(), is_reply) {
+                Ok((_, reply)) => return ReceiveResult::Use(Ok(reply.response)),
+                Err(m) => m,
+            };
+            match downcast_if(m, |down: &Down| down.monitor_ref == monitor_ref) {
+                Ok((_, down)) => ReceiveResult::Use(Err(CallError::Down(down.reason))),
+                Err(m) => {
+                    *msg = Some(m);
+                    ReceiveResult::Skip
+                }
+            }
+        },
+        timeout,
+    )
+    .await;
+
+    match res {
+        // The monitor is gone along with the called actor
+        Some(Err(e)) => Err(e),
+        Some(Ok(response)) => {
+            demonitor_flush(pid, monitor_ref).await;
+            Ok(response)
+        }
+        // The reply may still come later, and will then be dropped as the call
+        // is no longer pending
+        None => {
+            demonitor_flush(pid, monitor_ref).await;
+            Err(CallError::Timeout)
+        }
+    }
+}
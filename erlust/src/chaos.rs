@@ -0,0 +1,382 @@
+//! A [`Theater`] wrapper injecting faults, for testing code dealing with
+//! unreliable theaters
+
+use erased_serde::{Deserializer, Serialize as ErasedSerialize};
+use futures::future::FutureObj;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    connection_lost, spawn::spawn_detached, timer::Delay, ActorId, Error, Message, Theater,
+    TheaterBox,
+};
+
+/// How long [`Chaos`] holds back a message at most, see
+/// [`Chaos::reorder_rate`]
+const MAX_HOLD: Duration = Duration::from_millis(20);
+
+/// A message held back by [`Chaos`], to be sent after the next one
+struct Held {
+    id:      u64,
+    theater: Box<dyn TheaterBox>,
+    from:    ActorId,
+    to:      ActorId,
+    tag:     &'static str,
+    msg:     Vec<u8>,
+}
+
+struct ChaosState {
+    /// State of the splitmix64 generator
+    rng: u64,
+
+    drop_rate:      f64,
+    duplicate_rate: f64,
+    reorder_rate:   f64,
+    max_delay:      Duration,
+
+    /// Theaters the local theater is currently cut off from
+    partitioned: Vec<Box<dyn TheaterBox>>,
+
+    held:    Vec<Held>,
+    next_id: u64,
+}
+
+impl ChaosState {
+    fn next_u64(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns `true` with probability `p`
+    fn happens(&mut self, p: f64) -> bool {
+        let x = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        x < p
+    }
+
+    fn delay(&mut self) -> Duration {
+        let max = self.max_delay.as_nanos() as u64;
+        match max {
+            0 => Duration::from_secs(0),
+            max => Duration::from_nanos(self.next_u64() % (max + 1)),
+        }
+    }
+}
+
+/// What happens to a message sent through a [`ChaosTheater`]
+enum Fate {
+    Partitioned,
+    Dropped,
+    Held,
+    Sent { copies: usize },
+}
+
+/// A fault-injection policy, shared by the [`ChaosTheater`]s built with it
+///
+/// All the random decisions are taken with a generator seeded by the seed
+/// given to [`Chaos::new`], so that a sequence of messages sent through the
+/// same policy meets the same fate on each run.
+///
+/// Signals are never dropped, duplicated, delayed or reordered, as losing a
+/// signal can only happen when the connection is lost, but they are blocked
+/// by partitions.
+#[derive(Clone)]
+pub struct Chaos(Arc<Mutex<ChaosState>>);
+
+impl Chaos {
+    /// Builds a policy that lets all messages through, with random
+    /// decisions seeded by `seed`
+    pub fn new(seed: u64) -> Chaos {
+        Chaos(Arc::new(Mutex::new(ChaosState {
+            rng: seed,
+            drop_rate: 0.,
+            duplicate_rate: 0.,
+            reorder_rate: 0.,
+            max_delay: Duration::from_secs(0),
+            partitioned: Vec::new(),
+            held: Vec::new(),
+            next_id: 0,
+        })))
+    }
+
+    /// Drops each message with probability `p`
+    pub fn drop_rate(self, p: f64) -> Chaos {
+        self.0.lock().unwrap().drop_rate = p;
+        self
+    }
+
+    /// Sends each message twice with probability `p`
+    pub fn duplicate_rate(self, p: f64) -> Chaos {
+        self.0.lock().unwrap().duplicate_rate = p;
+        self
+    }
+
+    /// Holds back each message with probability `p`, sending it after the
+    /// next message sent to the same theater
+    ///
+    /// A message held back is sent anyway after 20ms if no other message is
+    /// sent to the same theater in the meantime.
+    pub fn reorder_rate(self, p: f64) -> Chaos {
+        self.0.lock().unwrap().reorder_rate = p;
+        self
+    }
+
+    /// Delays each message by a random duration up to `max`
+    ///
+    /// The sender waits for the message to be sent, so delays alone do not
+    /// reorder messages sent by the same actor.
+    pub fn max_delay(self, max: Duration) -> Chaos {
+        self.0.lock().unwrap().max_delay = max;
+        self
+    }
+
+    /// Cuts the traffic from the local theater to `theaters`
+    ///
+    /// Sending to them fails until [`Chaos::heal`] is called, and local
+    /// actors linked to or monitoring their actors through [`ChaosTheater`]s
+    /// using this policy are notified with
+    /// [`ExitReason::NoConnection`](crate::ExitReason).
+    ///
+    /// This only affects what is sent through [`ChaosTheater`]s, so messages
+    /// and signals sent by `theaters` are still received. A full partition
+    /// needs the remote theaters to be cut off from the local one too, eg.
+    /// by also passing the [`LoopbackTheater`](crate::LoopbackTheater)s of
+    /// the other direction when simulating theaters in the same process.
+    pub fn partition_one_way<T, I>(&self, theaters: I)
+    where
+        T: Theater,
+        I: IntoIterator<Item = T>,
+    {
+        for theater in theaters {
+            self.0
+                .lock()
+                .unwrap()
+                .partitioned
+                .push(Box::new(theater.clone()));
+            connection_lost(&ChaosTheater::new(theater, self.clone()));
+        }
+    }
+
+    /// Removes all the partitions
+    pub fn heal(&self) {
+        self.0.lock().unwrap().partitioned.clear();
+    }
+
+    fn is_partitioned(&self, theater: &dyn TheaterBox) -> bool {
+        let state = self.0.lock().unwrap();
+        state.partitioned.iter().any(|t| t.eq_box(theater))
+    }
+
+    /// Decides of the fate of a message sent to `theater`, and how long to
+    /// wait before it
+    fn decide(&self, theater: &dyn TheaterBox) -> (Fate, Duration) {
+        let mut state = self.0.lock().unwrap();
+        if state.partitioned.iter().any(|t| t.eq_box(theater)) {
+            return (Fate::Partitioned, Duration::from_secs(0));
+        }
+        let delay = state.delay();
+        let (drop_rate, reorder_rate, duplicate_rate) =
+            (state.drop_rate, state.reorder_rate, state.duplicate_rate);
+        let fate = if state.happens(drop_rate) {
+            Fate::Dropped
+        } else if state.happens(reorder_rate) {
+            Fate::Held
+        } else if state.happens(duplicate_rate) {
+            Fate::Sent { copies: 2 }
+        } else {
+            Fate::Sent { copies: 1 }
+        };
+        (fate, delay)
+    }
+
+    /// Holds back `msg`, until the next message sent to `theater` or
+    /// [`MAX_HOLD`], whichever comes first
+    fn hold(
+        &self,
+        theater: Box<dyn TheaterBox>,
+        from: ActorId,
+        to: ActorId,
+        tag: &'static str,
+        msg: Vec<u8>,
+    ) {
+        let id = {
+            let mut state = self.0.lock().unwrap();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.held.push(Held {
+                id,
+                theater,
+                from,
+                to,
+                tag,
+                msg,
+            });
+            id
+        };
+        let chaos = self.clone();
+        spawn_detached(async move {
+            Delay::new(MAX_HOLD).await;
+            let held = {
+                let mut state = chaos.0.lock().unwrap();
+                let i = state.held.iter().position(|h| h.id == id);
+                i.map(|i| state.held.remove(i))
+            };
+            if let Some(mut h) = held {
+                // Ignore errors, as the sender was already told the message
+                // was sent
+                let _ = h.theater.send(h.from, h.to, h.tag, h.msg).await;
+            }
+        });
+    }
+
+    /// Removes and returns the messages held back for `theater`
+    fn take_held(&self, theater: &dyn TheaterBox) -> Vec<Held> {
+        let mut state = self.0.lock().unwrap();
+        let (res, kept) = state
+            .held
+            .drain(..)
+            .partition(|h| h.theater.eq_box(theater));
+        state.held = kept;
+        res
+    }
+}
+
+/// A [`Theater`] that sends through `T`, injecting faults according to a
+/// [`Chaos`] policy
+///
+/// It is serialized like `T`, so remote theaters do not need to know about
+/// it, and deserializes without policy, ie. letting all messages through.
+/// Two `ChaosTheater`s are equal if they wrap equal theaters.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct ChaosTheater<T> {
+    inner: T,
+
+    #[serde(skip)]
+    chaos: Option<Chaos>,
+}
+
+impl<T: Theater> ChaosTheater<T> {
+    /// Wraps `inner`, injecting faults according to `chaos`
+    pub fn new(inner: T, chaos: Chaos) -> ChaosTheater<T> {
+        ChaosTheater {
+            inner,
+            chaos: Some(chaos),
+        }
+    }
+
+    /// Returns the wrapped theater
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn wrap(&self, inner: T) -> Box<ChaosTheater<T>> {
+        Box::new(ChaosTheater {
+            inner,
+            chaos: self.chaos.clone(),
+        })
+    }
+}
+
+impl<T: PartialEq> PartialEq for ChaosTheater<T> {
+    fn eq(&self, other: &ChaosTheater<T>) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<T: Theater> Message for ChaosTheater<T> {
+    fn tag() -> &'static str {
+        T::tag()
+    }
+}
+
+impl<T: Theater> Theater for ChaosTheater<T> {
+    fn here(&mut self) -> Result<Box<Self>, Error> {
+        let here = <T as Theater>::here(&mut self.inner)?;
+        Ok(self.wrap(*here))
+    }
+
+    fn sees_as(&mut self, other: Box<dyn TheaterBox>) -> Result<Box<Self>, Error> {
+        let other = match other.as_any().is::<ChaosTheater<T>>() {
+            true => {
+                let other = other.into_any().downcast::<ChaosTheater<T>>().unwrap();
+                Box::new(other.inner) as Box<dyn TheaterBox>
+            }
+            false => other,
+        };
+        let seen = <T as Theater>::sees_as(&mut self.inner, other)?;
+        Ok(self.wrap(*seen))
+    }
+
+    fn encode(
+        &mut self,
+        msg: &dyn ErasedSerialize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), erased_serde::Error> {
+        self.inner.encode(msg, out)
+    }
+
+    fn decode(
+        &mut self,
+        inp: &[u8],
+        visit: &mut dyn FnMut(&mut dyn Deserializer) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), erased_serde::Error> {
+        self.inner.decode(inp, visit)
+    }
+
+    fn send(
+        &mut self,
+        from: ActorId,
+        to: ActorId,
+        tag: &'static str,
+        msg: Vec<u8>,
+    ) -> FutureObj<'_, Result<(), Error>> {
+        FutureObj::new(Box::pin(async move {
+            let chaos = match self.chaos {
+                Some(ref chaos) => chaos.clone(),
+                None => return <T as Theater>::send(&mut self.inner, from, to, tag, msg).await,
+            };
+            let (fate, delay) = chaos.decide(&self.inner);
+            if delay > Duration::from_secs(0) {
+                Delay::new(delay).await;
+            }
+            let copies = match fate {
+                Fate::Partitioned => return Err(Error::transport("theaters are partitioned")),
+                Fate::Dropped => return Ok(()),
+                Fate::Held => {
+                    chaos.hold(Box::new(self.inner.clone()), from, to, tag, msg);
+                    return Ok(());
+                }
+                Fate::Sent { copies } => copies,
+            };
+            for _ in 1..copies {
+                <T as Theater>::send(&mut self.inner, from, to, tag, msg.clone()).await?;
+            }
+            <T as Theater>::send(&mut self.inner, from, to, tag, msg).await?;
+            for mut h in chaos.take_held(&self.inner) {
+                h.theater.send(h.from, h.to, h.tag, h.msg).await?;
+            }
+            Ok(())
+        }))
+    }
+
+    fn send_signal(
+        &mut self,
+        from: ActorId,
+        to: ActorId,
+        signal: Vec<u8>,
+    ) -> FutureObj<'_, Result<(), Error>> {
+        if let Some(ref chaos) = self.chaos {
+            if chaos.is_partitioned(&self.inner) {
+                return FutureObj::new(Box::pin(async {
+                    Err(Error::transport("theaters are partitioned"))
+                }));
+            }
+        }
+        <T as Theater>::send_signal(&mut self.inner, from, to, signal)
+    }
+}
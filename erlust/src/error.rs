@@ -0,0 +1,107 @@
+//! Errors returned by erlust
+
+use futures::{channel::mpsc::SendError, task::SpawnError};
+use std::{error, fmt};
+
+use crate::{InjectError, RegistryError};
+
+/// An error that happened while sending a message or spawning an actor
+#[derive(Debug)]
+pub enum Error {
+    /// The message could not be sent to a local actor, usually because it has
+    /// terminated
+    LocalSend(SendError),
+
+    /// The message could not be serialized or deserialized
+    Serialization(erased_serde::Error),
+
+    /// The theater could not transmit the message to the remote theater
+    Transport(Box<dyn error::Error + Send + Sync>),
+
+    /// The message could not be injected into a local actor
+    Inject(InjectError),
+
+    /// The name of the recipient could not be resolved, or the name of a
+    /// new actor could not be registered
+    Registry(RegistryError),
+
+    /// The executor could not spawn the actor
+    Spawn(SpawnError),
+}
+
+impl Error {
+    /// Wraps an error of a [`Theater`](crate::Theater) implementation
+    pub fn transport<E>(err: E) -> Error
+    where
+        E: Into<Box<dyn error::Error + Send + Sync>>,
+    {
+        Error::Transport(err.into())
+    }
+
+    /// Checks whether the error means the recipient has terminated
+    pub fn is_disconnected(&self) -> bool {
+        match self {
+            Error::LocalSend(e) => e.is_disconnected(),
+            Error::Inject(InjectError::NoSuchActor) | Error::Inject(InjectError::MailboxClosed) => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::LocalSend(e) => write!(f, "failed sending to a local actor: {}", e),
+            Error::Serialization(e) => write!(f, "failed serializing the message: {}", e),
+            Error::Transport(e) => write!(f, "theater transport error: {}", e),
+            Error::Inject(e) => write!(f, "failed injecting the message: {}", e),
+            Error::Registry(e) => write!(f, "registry error: {}", e),
+            Error::Spawn(e) => write!(f, "failed spawning the actor: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::LocalSend(e) => Some(e),
+            Error::Serialization(e) => Some(e),
+            Error::Transport(e) => Some(&**e),
+            Error::Inject(e) => Some(e),
+            Error::Registry(e) => Some(e),
+            Error::Spawn(e) => Some(e),
+        }
+    }
+}
+
+impl From<SendError> for Error {
+    fn from(e: SendError) -> Error {
+        Error::LocalSend(e)
+    }
+}
+
+impl From<erased_serde::Error> for Error {
+    fn from(e: erased_serde::Error) -> Error {
+        Error::Serialization(e)
+    }
+}
+
+impl From<InjectError> for Error {
+    fn from(e: InjectError) -> Error {
+        Error::Inject(e)
+    }
+}
+
+impl From<SpawnError> for Error {
+    fn from(e: SpawnError) -> Error {
+        Error::Spawn(e)
+    }
+}
+
+impl From<RegistryError> for Error {
+    fn from(e: RegistryError) -> Error {
+        Error::Registry(e)
+    }
+}
@@ -0,0 +1,102 @@
+//! Termination of actors, and how it propagates along links
+
+use futures::future;
+use std::any::Any;
+
+use crate::{signal::Signal, Message, Pid};
+
+/// The reason why an actor terminated
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ExitReason {
+    /// The actor's future completed
+    Normal,
+
+    /// The actor's future panicked, with the given panic message
+    Panic(String),
+
+    /// The actor was dropped by its executor before its future completed
+    Dropped,
+
+    /// The actor did not exist (any more) when trying to link to or monitor it
+    NoProc,
+
+    /// The connection to the theater of the actor was lost
+    NoConnection,
+
+    /// The actor was asked to shut down, eg. by its supervisor
+    Shutdown,
+
+    /// Reason to pass to [`Pid::exit`] to terminate an actor even if it traps
+    /// exits
+    ///
+    /// The actor then terminates with reason [`ExitReason::Killed`].
+    Kill,
+
+    /// The actor was terminated by [`ExitReason::Kill`]
+    Killed,
+
+    /// The waiting queue of the actor overflowed, and it was spawned with
+    /// [`Overflow::Kill`](crate::Overflow::Kill)
+    Overflow,
+
+    /// The actor was a [`Supervisor`](crate::Supervisor) that could not
+    /// spawn one of its children, for the given reason
+    StartFailed(String),
+}
+
+/// Message received by an actor that traps exits when a linked actor
+/// terminates
+///
+/// See [`trap_exit`].
+#[derive(Deserialize, Serialize)]
+pub struct Exit {
+    /// The actor that terminated
+    pub pid: Pid,
+
+    /// The reason why it terminated
+    pub reason: ExitReason,
+}
+
+impl Message for Exit {
+    fn tag() -> &'static str {
+        "erlust::Exit"
+    }
+}
+
+/// Sets whether the currently running actor traps exits
+///
+/// When an actor linked to the current actor terminates, if the current actor
+/// traps exits, it receives an [`Exit`] message in its mailbox. Otherwise,
+/// it terminates too with the same [`ExitReason`], unless said reason is
+/// [`ExitReason::Normal`].
+///
+/// This applies to all exit signals received after this call.
+///
+/// Panics if not called from an actor task.
+pub fn trap_exit(flag: bool) {
+    Pid::me().signal_local(Signal::TrapExit(flag));
+}
+
+/// Terminates the currently running actor with reason `reason`
+///
+/// The returned future never completes: the actor terminates as soon as it
+/// yields back to its executor. Contrary to [`Pid::exit`], this cannot be
+/// trapped.
+///
+/// Panics if not called from an actor task.
+pub async fn exit<T>(reason: ExitReason) -> T {
+    Pid::me().signal_local(Signal::Terminate(reason));
+    future::pending().await
+}
+
+/// Helper to get an [`ExitReason`] out of the payload of a panic
+pub fn panic_reason(payload: Box<dyn Any + Send>) -> ExitReason {
+    let msg = match payload.downcast::<String>() {
+        Ok(s) => *s,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(s) => String::from(*s),
+            Err(_) => String::from("Box<dyn Any>"),
+        },
+    };
+    ExitReason::Panic(msg)
+}
@@ -0,0 +1,92 @@
+//! Framing of the records exchanged by the stream-based theaters
+//!
+//! Each record is made of, in order, and with integers in big-endian:
+//!  * the `from` [`ActorId`], as a `u128`
+//!  * the `to` [`ActorId`], as a `u128`
+//!  * the length of the tag, as a `u32`
+//!  * the length of the message, as a `u32`
+//!  * the tag, in UTF-8
+//!  * the message
+
+use std::{
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{inject, ActorId, TheaterBox};
+
+/// Maximum size of a tag, to bound the memory a remote theater can make us
+/// allocate
+pub const MAX_TAG_SIZE: usize = 1024;
+
+/// Maximum size of a message, to bound the memory a remote theater can make
+/// us allocate
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// A record, as passed to [`Theater::send`](crate::Theater::send)
+pub struct Frame {
+    pub from: ActorId,
+    pub to:   ActorId,
+    pub tag:  String,
+    pub msg:  Vec<u8>,
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Serializes a record, ready to be written on the stream
+pub fn encode(from: ActorId, to: ActorId, tag: &str, msg: &[u8]) -> io::Result<Vec<u8>> {
+    if tag.len() > MAX_TAG_SIZE {
+        return Err(invalid("tag too long"));
+    }
+    if msg.len() > MAX_MESSAGE_SIZE {
+        return Err(invalid("message too long"));
+    }
+    let mut res = Vec::with_capacity(40 + tag.len() + msg.len());
+    res.extend_from_slice(&from.to_be_bytes());
+    res.extend_from_slice(&to.to_be_bytes());
+    res.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+    res.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+    res.extend_from_slice(tag.as_bytes());
+    res.extend_from_slice(msg);
+    Ok(res)
+}
+
+/// Reads a record from `r`
+pub async fn read<R: AsyncRead + Unpin>(r: &mut R) -> io::Result<Frame> {
+    let from = r.read_u128().await?;
+    let to = r.read_u128().await?;
+    let tag_len = r.read_u32().await? as usize;
+    let msg_len = r.read_u32().await? as usize;
+    if tag_len > MAX_TAG_SIZE || msg_len > MAX_MESSAGE_SIZE {
+        return Err(invalid("record too long"));
+    }
+    let mut tag = vec![0; tag_len];
+    r.read_exact(&mut tag).await?;
+    let tag = String::from_utf8(tag).map_err(|_| invalid("tag is not utf-8"))?;
+    let mut msg = vec![0; msg_len];
+    r.read_exact(&mut msg).await?;
+    Ok(Frame { from, to, tag, msg })
+}
+
+/// Reads records from `r` and injects them with `from_theater`, until the
+/// stream is closed or fails
+///
+/// Records that cannot be delivered are dropped, as the protocol has no way
+/// to tell it to the remote theater, and counted in `undelivered`.
+pub async fn inject_all<R: AsyncRead + Unpin>(
+    mut r: R,
+    from_theater: Box<dyn TheaterBox>,
+    undelivered: &AtomicU64,
+) {
+    while let Ok(Frame { from, to, tag, msg }) = read(&mut r).await {
+        if inject(from, to, tag, msg, from_theater.clone_to_box())
+            .await
+            .is_err()
+        {
+            undelivered.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
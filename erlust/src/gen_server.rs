@@ -0,0 +1,190 @@
+//! Servers handling calls, casts and other messages one at a time, see
+//! [`GenServer`]
+
+use futures::{task::Spawn, Future};
+use std::marker::PhantomData;
+
+use crate::{
+    call::generic_tag,
+    receive::{downcast, receive},
+    Accepts, Answers, Call, Caller, Error, ExitReason, Message, ReceiveResult, ReceivedMessage,
+    TypedPid,
+};
+
+/// What a [`GenServer`] should do after handling a message
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Next {
+    /// Wait for the next message
+    Continue,
+
+    /// Call [`GenServer::terminate`], then terminate with this reason
+    Stop(ExitReason),
+}
+
+/// An actor that owns a state, and handles the messages it receives one at a
+/// time
+///
+/// The server is run by [`serve`], usually through [`GenServerPid::spawn`],
+/// and is reached through a [`GenServerPid`]. Messages are handled in the
+/// order they are received:
+///  - Calls, made with [`GenServerPid::call`], are given to
+///    [`GenServer::handle_call`], whose reply is sent back to the caller,
+///    unless it chose to reply later,
+///  - Casts, made with [`GenServerPid::cast`], are given to
+///    [`GenServer::handle_cast`],
+///  - All other messages, including [`Exit`](crate::Exit) messages if the
+///    server traps exits, are given to [`GenServer::handle_info`].
+///
+/// [`GenServer::terminate`] is called when a handler asks the server to stop.
+/// It is not called if the server is killed by an exit signal, or if a
+/// handler panics.
+pub trait GenServer: 'static + Send + Sized {
+    /// The requests accepted by [`GenServer::handle_call`]
+    type Call: Message;
+
+    /// The replies to [`GenServer::Call`]s
+    type Reply: Message;
+
+    /// The messages accepted by [`GenServer::handle_cast`]
+    type Cast: Message;
+
+    /// Called once, from the server actor, before handling any message
+    ///
+    /// If it fails, the server terminates with the returned reason, without
+    /// calling [`GenServer::terminate`].
+    fn init(&mut self) -> impl Send + Future<Output = Result<(), ExitReason>> {
+        async { Ok(()) }
+    }
+
+    /// Handles `request`, made by `from`, and returns the reply to send to
+    /// `from`
+    ///
+    /// The handler can instead return `None`, and reply later with
+    /// [`Caller::reply`], eg. once it has handled other messages, or from
+    /// another actor it gave `from` to. The caller keeps waiting until then,
+    /// or until the call times out.
+    fn handle_call(
+        &mut self,
+        request: Self::Call,
+        from: &Caller,
+    ) -> impl Send + Future<Output = (Option<Self::Reply>, Next)>;
+
+    /// Handles `msg`
+    fn handle_cast(&mut self, msg: Self::Cast) -> impl Send + Future<Output = Next> {
+        let _ = msg;
+        async { Next::Continue }
+    }
+
+    /// Handles `msg`, that is neither a call nor a cast
+    ///
+    /// By default, such messages are dropped.
+    fn handle_info(&mut self, msg: ReceivedMessage) -> impl Send + Future<Output = Next> {
+        let _ = msg;
+        async { Next::Continue }
+    }
+
+    /// Called when a handler returned [`Next::Stop`], right before the
+    /// server terminates with `reason`
+    fn terminate(&mut self, reason: &ExitReason) -> impl Send + Future<Output = ()> {
+        let _ = reason;
+        async {}
+    }
+}
+
+/// A message sent with [`GenServerPid::cast`]
+#[derive(Deserialize, Serialize)]
+struct Cast<M>(M);
+
+impl<M: Message> Message for Cast<M> {
+    fn tag() -> &'static str {
+        generic_tag("erlust::Cast", M::tag())
+    }
+}
+
+/// A message received by a [`GenServer`]
+enum Event<S: GenServer> {
+    Call(Box<Call<S::Call>>),
+    Cast(Box<Cast<S::Cast>>),
+    Info(ReceivedMessage),
+}
+
+/// Waits for the next message, and sorts it for [`GenServer`] `S`
+async fn next_event<S: GenServer>() -> Event<S> {
+    receive(async |msg: &mut Option<ReceivedMessage>| {
+        let msg = match downcast::<Call<S::Call>>(msg.take().unwrap()) {
+            Ok((_, call)) => return ReceiveResult::Use(Event::Call(call)),
+            Err(msg) => msg,
+        };
+        ReceiveResult::Use(match downcast::<Cast<S::Cast>>(msg) {
+            Ok((_, cast)) => Event::Cast(cast),
+            Err(msg) => Event::Info(msg),
+        })
+    })
+    .await
+}
+
+/// Runs `server` until it stops
+///
+/// The returned future is meant to be spawned as an actor, eg. as the start
+/// function of a [`ChildSpec`](crate::ChildSpec).
+pub async fn serve<S: GenServer>(mut server: S) {
+    if let Err(reason) = server.init().await {
+        return crate::exit(reason).await;
+    }
+    loop {
+        let next = match next_event::<S>().await {
+            Event::Call(call) => {
+                let Call { from, request } = *call;
+                let (reply, next) = server.handle_call(request, &from).await;
+                if let Some(reply) = reply {
+                    // The caller may have given up on the call already
+                    let _ = from.reply(reply).await;
+                }
+                next
+            }
+            Event::Cast(cast) => server.handle_cast(cast.0).await,
+            Event::Info(msg) => server.handle_info(msg).await,
+        };
+        if let Next::Stop(reason) = next {
+            server.terminate(&reason).await;
+            return crate::exit(reason).await;
+        }
+    }
+}
+
+/// The protocol of actors running [`GenServer`] `S`, see [`GenServerPid`]
+///
+/// It answers the [`GenServer::Call`]s of `S`, and accepts the messages `S`
+/// itself declares it [`Accepts`], eg. to be handled by
+/// [`GenServer::handle_info`].
+pub struct GenServerProtocol<S> {
+    phantom: PhantomData<fn() -> S>,
+}
+
+impl<S: GenServer> Answers<S::Call> for GenServerProtocol<S> {
+    type Reply = S::Reply;
+}
+
+impl<S: GenServer + Accepts<M>, M: Message> Accepts<M> for GenServerProtocol<S> {}
+
+/// The address of an actor running [`GenServer`] `S`
+///
+/// Its [`call`](TypedPid::call)s are answered by [`GenServer::handle_call`].
+pub type GenServerPid<S> = TypedPid<GenServerProtocol<S>>;
+
+impl<S: GenServer> TypedPid<GenServerProtocol<S>> {
+    /// Spawns `server` as a new actor, on the default executor
+    pub fn spawn(server: S) -> Result<GenServerPid<S>, Error> {
+        crate::spawn(serve(server)).map(GenServerPid::from_pid)
+    }
+
+    /// Spawns `server` as a new actor, on `spawner`
+    pub fn spawn_on<Spwn: Spawn>(spawner: &mut Spwn, server: S) -> Result<GenServerPid<S>, Error> {
+        crate::spawn_on(spawner, serve(server)).map(GenServerPid::from_pid)
+    }
+
+    /// Sends `msg` to the server, without waiting for it to be handled
+    pub async fn cast(&self, msg: S::Cast) -> Result<(), Error> {
+        self.pid().clone().send(Box::new(Cast(msg))).await
+    }
+}
@@ -0,0 +1,444 @@
+//! Registry of names shared by all the connected theaters
+//!
+//! Each theater runs a [`GlobalRegistry`] actor, that is told about the
+//! registries of other theaters with [`add_peer`]. Registries then replicate
+//! to each other the names registered in their theater, and drop the names
+//! registered by a peer as soon as the connection to it is lost.
+//!
+//! The functions ending in `_in` talk to a given registry rather than to the
+//! one of the local theater. They are mostly useful to run several registries
+//! in a single process, eg. to test them with
+//! [`LoopbackTheater`](crate::LoopbackTheater)s.
+//!
+//! Requests to a registry are [`Pid::call`]s, that fail with
+//! [`RegistryError::Timeout`] if it does not answer within [`TIMEOUT`].
+
+use futures::task::{Spawn, SpawnExt};
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    receive::{downcast, receive},
+    Call, CallError, Down, Error, LocalChannelUpdater, Message, Pid, ReceiveResult,
+    ReceivedMessage, Ref, RegistryError,
+};
+
+/// The name under which the [`GlobalRegistry`] is registered in the local
+/// registry (see [`register`](crate::register))
+pub const REGISTRY_NAME: &str = "erlust::global";
+
+/// How long to wait for a registry to answer a request
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Function called to resolve a name clash between two actors, that returns
+/// the actor that should keep the name, if any
+///
+/// Name clashes happen when two theaters that each registered the same name
+/// get connected, eg. when a network partition heals. The resolver is called
+/// on both sides, with the arguments swapped, so it must return the same
+/// result whatever the order of its arguments. Actors that lose the name are
+/// not notified.
+pub type Resolver = dyn Send + Sync + Fn(&str, &Pid, &Pid) -> Option<Pid>;
+
+/// The default [`Resolver`], that unregisters both actors
+pub fn unregister_both(_name: &str, _a: &Pid, _b: &Pid) -> Option<Pid> {
+    None
+}
+
+/// Who is responsible for a binding
+enum Owner {
+    /// The local registry, that monitors the actor with this [`Ref`]
+    Local(Ref),
+
+    /// The registry of another theater
+    Peer(Pid),
+}
+
+struct Binding {
+    pid:   Pid,
+    owner: Owner,
+}
+
+/// Requests from local actors to the local registry
+#[derive(Deserialize, Serialize)]
+enum Request {
+    Register(String, Pid),
+    Unregister(String),
+    Whereis(String),
+    AddPeer(Pid),
+}
+
+impl Message for Request {
+    fn tag() -> &'static str {
+        "erlust::global::Request"
+    }
+}
+
+/// The answer of the registry to a [`Request`]
+#[derive(Deserialize, Serialize)]
+struct Answer(Result<Option<Pid>, RegistryError>);
+
+impl Message for Answer {
+    fn tag() -> &'static str {
+        "erlust::global::Answer"
+    }
+}
+
+/// Messages exchanged between registries, that only carry the bindings owned
+/// by the sending registry
+#[derive(Clone, Deserialize, Serialize)]
+enum Update {
+    /// Sent when connecting to a peer, that answers with `Welcome`
+    Hello(Vec<(String, Pid)>),
+    Welcome(Vec<(String, Pid)>),
+    Add(String, Pid),
+    Remove(String),
+}
+
+impl Message for Update {
+    fn tag() -> &'static str {
+        "erlust::global::Update"
+    }
+}
+
+enum Event {
+    Call(Call<Request>),
+    Update(Pid, Update),
+    Down(Down),
+}
+
+/// Waits for the next message, returning it if the registry handles it
+async fn next_event() -> Option<Event> {
+    receive(async |msg: &mut Option<ReceivedMessage>| {
+        let msg = msg.take().unwrap();
+        // Only local actors can make requests to the registry
+        let msg = match msg {
+            ReceivedMessage::Local(_) => match downcast::<Call<Request>>(msg) {
+                Ok((_, call)) => return ReceiveResult::Use(Some(Event::Call(*call))),
+                Err(msg) => msg,
+            },
+            msg => msg,
+        };
+        let msg = match downcast::<Update>(msg) {
+            Ok((from, update)) => return ReceiveResult::Use(Some(Event::Update(from, *update))),
+            Err(msg) => msg,
+        };
+        ReceiveResult::Use(downcast::<Down>(msg).ok().map(|(_, d)| Event::Down(*d)))
+    })
+    .await
+}
+
+/// The actor replicating names across theaters
+pub struct GlobalRegistry {
+    resolver: Box<Resolver>,
+    names:    HashMap<String, Binding>,
+    peers:    Vec<(Ref, Pid)>,
+}
+
+impl GlobalRegistry {
+    /// Builds a registry that resolves name clashes with [`unregister_both`]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> GlobalRegistry {
+        GlobalRegistry {
+            resolver: Box::new(unregister_both),
+            names:    HashMap::new(),
+            peers:    Vec::new(),
+        }
+    }
+
+    /// Sets the function used to resolve name clashes
+    pub fn resolver<F>(mut self, resolver: F) -> GlobalRegistry
+    where
+        F: 'static + Send + Sync + Fn(&str, &Pid, &Pid) -> Option<Pid>,
+    {
+        self.resolver = Box::new(resolver);
+        self
+    }
+
+    /// Spawns the registry as an actor with `spawner`, as the registry of the
+    /// local theater
+    ///
+    /// The registry is usable as soon as this function returns. It does
+    /// nothing if a registry is already running in the local theater.
+    pub fn spawn<Spwn: Spawn>(self, spawner: &mut Spwn) -> Result<(), Error> {
+        let task = LocalChannelUpdater::new(self.run());
+        if crate::register(REGISTRY_NAME, &task.pid()).is_err() {
+            return Ok(());
+        }
+        spawner.spawn(task).map_err(|e| {
+            // Never-polled actors do not unregister on their own
+            let _ = crate::unregister(REGISTRY_NAME);
+            Error::Spawn(e)
+        })
+    }
+
+    /// Spawns the registry as an actor with `spawner`, without making it the
+    /// registry of the local theater, and returns its [`Pid`]
+    ///
+    /// The registry is then only reachable through the functions ending in
+    /// `_in`, like [`register_in`].
+    pub fn spawn_unnamed<Spwn: Spawn>(self, spawner: &mut Spwn) -> Result<Pid, Error> {
+        crate::spawn_on(spawner, self.run())
+    }
+
+    async fn run(mut self) {
+        loop {
+            match next_event().await {
+                Some(Event::Call(Call { from, request })) => {
+                    let answer = Answer(self.handle_request(request).await);
+                    // Ignore errors, as they mean the caller is gone
+                    let _ = from.reply(answer).await;
+                }
+                Some(Event::Update(from, update)) => self.handle_update(from, update).await,
+                Some(Event::Down(down)) => self.handle_down(down).await,
+                None => (),
+            }
+        }
+    }
+
+    async fn handle_request(&mut self, request: Request) -> Result<Option<Pid>, RegistryError> {
+        match request {
+            Request::Register(name, pid) => {
+                if self.names.contains_key(&name) {
+                    return Err(RegistryError::NameTaken);
+                }
+                if self.names.values().any(|b| b.pid == pid) {
+                    return Err(RegistryError::AlreadyRegistered);
+                }
+                let monitor_ref = pid.monitor().await;
+                let owner = Owner::Local(monitor_ref);
+                self.names.insert(
+                    name.clone(),
+                    Binding {
+                        pid: pid.clone(),
+                        owner,
+                    },
+                );
+                self.broadcast(Update::Add(name, pid)).await;
+                Ok(None)
+            }
+            Request::Unregister(name) => match self.names.get(&name) {
+                None => Err(RegistryError::NotRegistered),
+                Some(Binding {
+                    owner: Owner::Peer(_),
+                    ..
+                }) => Err(RegistryError::NotLocal),
+                Some(_) => {
+                    let binding = self.names.remove(&name).unwrap();
+                    self.dropped(name, binding).await;
+                    Ok(None)
+                }
+            },
+            Request::Whereis(name) => Ok(self.names.get(&name).map(|b| b.pid.clone())),
+            Request::AddPeer(peer) => {
+                if !self.peers.iter().any(|(_, p)| *p == peer) {
+                    let monitor_ref = peer.monitor().await;
+                    self.peers.push((monitor_ref, peer.clone()));
+                    let hello = Update::Hello(self.owned());
+                    // Ignore errors, as the monitor will notice the peer is gone
+                    let _ = peer.clone().send(Box::new(hello)).await;
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    async fn handle_update(&mut self, mut from: Pid, update: Update) {
+        // Updates from registries that were not added as peers are ignored,
+        // as they cannot be trusted
+        if !self.peers.iter().any(|(_, p)| *p == from) {
+            return;
+        }
+        match update {
+            Update::Hello(bindings) => {
+                let welcome = Update::Welcome(self.owned());
+                // Ignore errors, as the monitor will notice the peer is gone
+                let _ = from.send(Box::new(welcome)).await;
+                self.merge(&from, bindings).await;
+            }
+            Update::Welcome(bindings) => self.merge(&from, bindings).await,
+            Update::Add(name, pid) => self.merge(&from, vec![(name, pid)]).await,
+            Update::Remove(name) => {
+                if let Some(Binding {
+                    owner: Owner::Peer(p),
+                    ..
+                }) = self.names.get(&name)
+                {
+                    if *p == from {
+                        self.names.remove(&name);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_down(&mut self, down: Down) {
+        if let Some(idx) = self.peers.iter().position(|(r, _)| *r == down.monitor_ref) {
+            let (_, peer) = self.peers.swap_remove(idx);
+            self.names.retain(|_, b| match b.owner {
+                Owner::Peer(ref p) => *p != peer,
+                Owner::Local(_) => true,
+            });
+            return;
+        }
+        let name = self.names.iter().find_map(|(name, b)| match b.owner {
+            Owner::Local(r) if r == down.monitor_ref => Some(name.clone()),
+            _ => None,
+        });
+        if let Some(name) = name {
+            self.names.remove(&name);
+            self.broadcast(Update::Remove(name)).await;
+        }
+    }
+
+    /// Adds the bindings owned by `peer`, resolving name clashes
+    async fn merge(&mut self, peer: &Pid, bindings: Vec<(String, Pid)>) {
+        for (name, pid) in bindings {
+            let winner = match self.names.get(&name) {
+                None => Some(pid.clone()),
+                Some(b) if b.pid == pid => continue,
+                Some(b) => (self.resolver)(&name, &b.pid, &pid),
+            };
+            if winner.is_some() && winner.as_ref() == self.names.get(&name).map(|b| &b.pid) {
+                continue;
+            }
+            if let Some(binding) = self.names.remove(&name) {
+                self.dropped(name.clone(), binding).await;
+            }
+            if winner.as_ref() == Some(&pid) {
+                self.names.insert(
+                    name,
+                    Binding {
+                        pid,
+                        owner: Owner::Peer(peer.clone()),
+                    },
+                );
+            }
+        }
+    }
+
+    /// Cleans up after `binding` was removed from the names
+    async fn dropped(&mut self, name: String, binding: Binding) {
+        if let Owner::Local(monitor_ref) = binding.owner {
+            binding.pid.demonitor(monitor_ref).await;
+            self.broadcast(Update::Remove(name)).await;
+        }
+    }
+
+    /// Returns the bindings owned by the local registry
+    fn owned(&self) -> Vec<(String, Pid)> {
+        self.names
+            .iter()
+            .filter(|(_, b)| matches!(b.owner, Owner::Local(_)))
+            .map(|(name, b)| (name.clone(), b.pid.clone()))
+            .collect()
+    }
+
+    async fn broadcast(&self, update: Update) {
+        for (_, peer) in &self.peers {
+            // Ignore errors, as the monitor will notice the peer is gone
+            let _ = peer.clone().send(Box::new(update.clone())).await;
+        }
+    }
+}
+
+/// Returns the registry of the local theater
+fn local() -> Result<Pid, RegistryError> {
+    crate::whereis(REGISTRY_NAME).ok_or(RegistryError::NotStarted)
+}
+
+/// Sends `request` to `registry` and waits for its answer
+///
+/// Fails with [`RegistryError::NotStarted`] if `registry` terminates before
+/// answering, and with [`RegistryError::Timeout`] if it does not answer
+/// within [`TIMEOUT`].
+async fn call(registry: &Pid, request: Request) -> Result<Option<Pid>, RegistryError> {
+    match registry.call(request, TIMEOUT).await {
+        Ok(Answer(result)) => result,
+        Err(CallError::Timeout) => Err(RegistryError::Timeout),
+        Err(CallError::Down(_)) | Err(CallError::Send(_)) => Err(RegistryError::NotStarted),
+    }
+}
+
+/// Registers `pid` under `name` in all the connected theaters
+///
+/// Each name can be used by at most one actor, and each actor can be
+/// registered under at most one name. The name is automatically unregistered
+/// when the actor terminates.
+///
+/// Panics if not called from an actor task.
+pub async fn register(name: &str, pid: &Pid) -> Result<(), RegistryError> {
+    register_in(&local()?, name, pid).await
+}
+
+/// Same as [`register`], with `registry` instead of the local registry
+///
+/// Panics if not called from an actor task.
+pub async fn register_in(registry: &Pid, name: &str, pid: &Pid) -> Result<(), RegistryError> {
+    let request = Request::Register(String::from(name), pid.clone());
+    call(registry, request).await.map(|_| ())
+}
+
+/// Removes the registration of `name`
+///
+/// Only the names registered from the local theater can be unregistered.
+///
+/// Panics if not called from an actor task.
+pub async fn unregister(name: &str) -> Result<(), RegistryError> {
+    unregister_in(&local()?, name).await
+}
+
+/// Same as [`unregister`], with `registry` instead of the local registry
+///
+/// Panics if not called from an actor task.
+pub async fn unregister_in(registry: &Pid, name: &str) -> Result<(), RegistryError> {
+    let request = Request::Unregister(String::from(name));
+    call(registry, request).await.map(|_| ())
+}
+
+/// Returns the actor registered under `name` in any connected theater
+///
+/// Returns `None` if the local registry is not running.
+///
+/// Panics if not called from an actor task.
+pub async fn whereis(name: &str) -> Option<Pid> {
+    whereis_in(&local().ok()?, name).await
+}
+
+/// Same as [`whereis`], with `registry` instead of the local registry
+///
+/// Panics if not called from an actor task.
+pub async fn whereis_in(registry: &Pid, name: &str) -> Option<Pid> {
+    let request = Request::Whereis(String::from(name));
+    call(registry, request).await.ok().and_then(|pid| pid)
+}
+
+/// Sends `msg` to the actor registered under `name` in any connected theater
+///
+/// Fails with [`RegistryError::NotRegistered`] if no actor is registered
+/// under `name`, and otherwise like [`Pid::send`].
+///
+/// Panics if not called from an actor task.
+pub async fn send<M: Message>(name: &str, msg: Box<M>) -> Result<(), Error> {
+    let mut pid = whereis(name).await.ok_or(RegistryError::NotRegistered)?;
+    pid.send(msg).await
+}
+
+/// Connects the local registry to `peer`, the registry of another theater
+///
+/// The two registries start replicating their names once both have been
+/// connected to each other. Names registered by `peer` are dropped when the
+/// connection to it is lost.
+///
+/// Panics if not called from an actor task.
+pub async fn add_peer(peer: &Pid) -> Result<(), RegistryError> {
+    add_peer_in(&local()?, peer).await
+}
+
+/// Same as [`add_peer`], with `registry` instead of the local registry
+///
+/// Panics if not called from an actor task.
+pub async fn add_peer_in(registry: &Pid, peer: &Pid) -> Result<(), RegistryError> {
+    call(registry, Request::AddPeer(peer.clone()))
+        .await
+        .map(|_| ())
+}
@@ -1,8 +1,40 @@
-//! Helper for sending a message for local actors
+//! Helpers for theaters to forward what they receive to local actors
 
-use futures::SinkExt;
+use std::{error, fmt};
 
-use crate::{ActorId, Pid, ReceivedMessage, RemoteMessage, TheaterBox, LOCAL_SENDERS};
+use crate::{
+    signal::{RemoteSignal, Signal, SIGNAL_TAG},
+    ActorId, ExitReason, Pid, ReceivedMessage, RemoteMessage, TheaterBox, LOCAL_SENDERS,
+};
+
+/// Error returned by [`inject`] when a message could not be delivered
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InjectError {
+    /// There is no actor with this [`ActorId`] (any longer)
+    NoSuchActor,
+
+    /// The actor is terminating, and no longer accepts messages
+    ///
+    /// This only happens when the actor terminates while the message is being
+    /// injected: once it has terminated, [`InjectError::NoSuchActor`] is
+    /// returned instead.
+    MailboxClosed,
+
+    /// The actor's mailbox is full
+    MailboxFull,
+}
+
+impl fmt::Display for InjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            InjectError::NoSuchActor => "no such actor",
+            InjectError::MailboxClosed => "mailbox closed",
+            InjectError::MailboxFull => "mailbox full",
+        })
+    }
+}
+
+impl error::Error for InjectError {}
 
 /// Injects a message from another theater to a local actor
 ///
@@ -13,6 +45,9 @@ use crate::{ActorId, Pid, ReceivedMessage, RemoteMessage, TheaterBox, LOCAL_SEND
 ///  * `tag` is a tag that identifies the message type
 ///  * `msg` is the (serialized) message
 ///
+/// Signals sent with [`Theater::send_signal`] must be injected with this
+/// function too, using the tag used by the remote theater.
+///
 /// `from_theater` ***must not*** be taken as trusted from the remote theater! This would break the
 /// security model of erlust, which is based on the fact that theaters don't necessarily trust
 /// other theaters (but actors within a theater trust each other).
@@ -20,6 +55,13 @@ use crate::{ActorId, Pid, ReceivedMessage, RemoteMessage, TheaterBox, LOCAL_SEND
 /// As a consequence, `from_theater` ***must*** be computed locally based on the way the message
 /// has been received. For instance, if it came from a TLS connection, `from_theater` can be
 /// inferred from the connection parameters to identify the theater on the other side.
+///
+/// This never waits for the actor to have room in its mailbox, so that a single slow actor cannot
+/// block the whole connection: it fails with [`InjectError::MailboxFull`] instead, and the
+/// theater can then tell the remote theater the message was not delivered.
+///
+/// Signals for actors that do not exist fail with [`InjectError::NoSuchActor`], after the remote
+/// actor has been notified when relevant. Invalid signals are ignored.
 // TODO: (C) this 'static shouldn't be needed, it's in TheaterBox's recursive
 // bounds
 pub async fn inject(
@@ -28,14 +70,81 @@ pub async fn inject(
     tag: String,
     msg: Vec<u8>,
     from_theater: Box<dyn 'static + TheaterBox>,
-) {
-    // TODO: (A) do not panic if the local sender doesn't exist
-    let mut sender = LOCAL_SENDERS.read().unwrap().get(to).unwrap();
-    sender
-        .send(ReceivedMessage::Remote((
-            Pid::__remote(from, from_theater),
-            RemoteMessage { tag, msg },
-        )))
-        .await
-        .unwrap(); // TODO: (A) do not panic if injection fails
+) -> Result<(), InjectError> {
+    if tag == SIGNAL_TAG {
+        return inject_signal(from, to, msg, from_theater).await;
+    }
+
+    let msg = ReceivedMessage::Remote((
+        Pid::__remote(from, from_theater),
+        RemoteMessage { tag, msg },
+    ));
+    let injector = LOCAL_SENDERS.read().unwrap().injector(to);
+    let injector = injector.ok_or(InjectError::NoSuchActor)?;
+    let res = injector.lock().unwrap().try_send(msg);
+    res.map_err(|e| match e.is_full() {
+        true => InjectError::MailboxFull,
+        false => InjectError::MailboxClosed,
+    })
+}
+
+/// Injects a signal from another theater to a local actor
+///
+/// If the local actor does not exist (any longer), the remote actor is
+/// notified with reason [`ExitReason::NoProc`] when relevant.
+async fn inject_signal(
+    from: ActorId,
+    to: ActorId,
+    msg: Vec<u8>,
+    mut from_theater: Box<dyn 'static + TheaterBox>,
+) -> Result<(), InjectError> {
+    let mut signal = None;
+    let status = from_theater.decode(&msg, &mut |d| {
+        signal = Some(erased_serde::deserialize::<RemoteSignal>(d)?);
+        Ok(())
+    });
+    let signal = match (status, signal) {
+        (Ok(()), Some(s)) => s,
+        // Ignore invalid signals, as there is no one to report them to
+        _ => return Ok(()),
+    };
+    let from_pid = Pid::__remote(from, from_theater.clone_to_box());
+    let signals = LOCAL_SENDERS.read().unwrap().get_signals(to);
+    let sent = match signals {
+        Some(signals) => match signals.unbounded_send(signal.into_signal(from_pid)) {
+            Ok(()) => return Ok(()),
+            Err(e) => e.into_inner(),
+        },
+        None => signal.into_signal(from_pid),
+    };
+
+    // `to` does not exist, tell it to the remote actor if it is waiting for
+    // an answer
+    let answer = match sent {
+        Signal::Link(_) => RemoteSignal::Exit(ExitReason::NoProc),
+        Signal::Monitor(monitor_ref, _) => RemoteSignal::Down(monitor_ref, ExitReason::NoProc),
+        _ => return Err(InjectError::NoSuchActor),
+    };
+    let mut vec = Vec::with_capacity(32);
+    if from_theater.encode(&answer, &mut vec).is_err() {
+        return Err(InjectError::NoSuchActor);
+    }
+    // Ignore errors, as there is no one left to report them to
+    let _ = from_theater.send_signal(to, from, vec).await;
+    Err(InjectError::NoSuchActor)
+}
+
+/// Notifies local actors that the connection to `theater` has been lost
+///
+/// All local actors linked to or monitoring actors of `theater` will receive
+/// an exit signal or a [`Down`](crate::Down) message with reason
+/// [`ExitReason::NoConnection`].
+///
+/// This should be called by [`Theater`](crate::Theater) implementations when they detect
+/// that a remote theater is no longer reachable.
+pub fn connection_lost(theater: &dyn TheaterBox) {
+    for signals in LOCAL_SENDERS.read().unwrap().all_signals() {
+        // Ignore errors, as they only mean the actor has already terminated
+        let _ = signals.unbounded_send(Signal::NoConnection(theater.clone_to_box()));
+    }
 }
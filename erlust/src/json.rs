@@ -0,0 +1,21 @@
+//! JSON encoding of messages, for the theaters provided by erlust
+
+use erased_serde::{Deserializer, Serialize as ErasedSerialize, Serializer};
+use serde::de::Error as SerdeDeError;
+
+/// Serializes `msg` as JSON into `out`, see [`Theater::encode`](crate::Theater::encode)
+pub fn encode(msg: &dyn ErasedSerialize, out: &mut Vec<u8>) -> Result<(), erased_serde::Error> {
+    let mut json = serde_json::Serializer::new(out);
+    msg.erased_serialize(&mut <dyn Serializer>::erase(&mut json))?;
+    Ok(())
+}
+
+/// Deserializes JSON from `inp`, see [`Theater::decode`](crate::Theater::decode)
+pub fn decode(
+    inp: &[u8],
+    visit: &mut dyn FnMut(&mut dyn Deserializer) -> Result<(), erased_serde::Error>,
+) -> Result<(), erased_serde::Error> {
+    let mut json = serde_json::Deserializer::from_slice(inp);
+    visit(&mut <dyn Deserializer>::erase(&mut json))?;
+    json.end().map_err(erased_serde::Error::custom)
+}
@@ -1,49 +1,113 @@
 #[macro_use]
 extern crate erased_serde;
-extern crate failure;
 extern crate futures;
+extern crate futures_timer;
+extern crate getrandom;
 #[macro_use]
 extern crate lazy_static;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(feature = "tokio")]
+extern crate erlust_derive;
+#[cfg(any(feature = "loopback", feature = "tcp", feature = "unix"))]
+extern crate serde_json;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tls")]
+extern crate tokio_rustls;
+#[cfg(feature = "tls")]
+extern crate x509_parser;
 
+mod call;
+mod chaos;
+mod error;
+mod exit;
+#[cfg(any(feature = "tcp", feature = "unix"))]
+mod frame;
+mod gen_server;
+pub mod global;
 mod inject;
+#[cfg(any(feature = "loopback", feature = "tcp", feature = "unix"))]
+mod json;
 mod local_channel;
 mod local_channel_updater;
 mod local_senders;
+#[cfg(feature = "loopback")]
+mod loopback;
+mod monitor;
 mod pid;
 mod receive;
+mod registry;
+#[cfg(feature = "tokio")]
+mod runtime;
+mod signal;
 mod spawn;
+mod state_machine;
+#[cfg(any(feature = "tcp", feature = "unix"))]
+mod stream;
+mod supervisor;
+#[cfg(feature = "tcp")]
+mod tcp;
 mod theater;
+mod timer;
+#[cfg(feature = "tls")]
+mod tls;
+mod typed_pid;
 mod types;
+#[cfg(feature = "unix")]
+mod unix;
 
 use self::{
     local_channel::{LocalChannel, MY_CHANNEL},
     local_channel_updater::LocalChannelUpdater,
     local_senders::LOCAL_SENDERS,
-    theater::{TheaterBox, HERE},
-    types::{ActorId, LocalReceiver, LocalSender},
+    theater::HERE,
+    types::{LocalReceiver, LocalSender, SystemSender},
 };
 
 pub use futures::{channel::mpsc::SendError, task::SpawnError};
 
 pub use self::{
-    inject::inject,
+    call::{Call, CallError, Caller},
+    chaos::{Chaos, ChaosTheater},
+    error::Error,
+    exit::{exit, trap_exit, Exit, ExitReason},
+    gen_server::{serve, GenServer, GenServerPid, GenServerProtocol, Next},
+    inject::{connection_lost, inject, InjectError},
+    monitor::{Down, Ref},
     pid::Pid,
-    receive::{receive, ReceiveResult},
-    spawn::spawn,
-    theater::Theater,
-    types::{LocalMessage, Message, ReceivedMessage, RemoteMessage},
+    receive::{__deserialize_remote, receive, receive_timeout, ReceiveResult},
+    registry::{register, send_named, unregister, whereis, RegistryError},
+    spawn::{set_executor, spawn, spawn_link, spawn_link_on, spawn_on, Overflow, SpawnOptions},
+    state_machine::{run_state_machine, Handles, StateEvent, StateMachine, Transition},
+    supervisor::{ChildSpec, Restart, Strategy, Supervisor},
+    theater::{Theater, TheaterBox},
+    typed_pid::{Accepts, Answers, TypedPid},
+    types::{ActorId, LocalMessage, Message, ReceivedMessage, RemoteMessage},
 };
 
-// TODO: (A) add a local registry to record name<->Pid associations?
-// TODO: (B) write a library offering a global registry for name<->Pid
+#[cfg(feature = "tokio")]
+pub use erlust_derive::main;
+
+#[cfg(feature = "tokio")]
+pub use self::runtime::{__run_main, spawn_blocking, TokioSpawner};
+
+#[cfg(feature = "loopback")]
+pub use self::loopback::LoopbackTheater;
+
+#[cfg(feature = "tcp")]
+pub use self::tcp::TcpTheater;
+
+#[cfg(feature = "tls")]
+pub use self::tls::TlsTheater;
+#[cfg(feature = "tls")]
+pub use tokio_rustls::rustls;
+
+#[cfg(feature = "unix")]
+pub use self::unix::UnixTheater;
 
 // TODO: (A) document all the things
 // TODO: (A) test all the things
 
-// TODO: (A) implement links & monitors
-// TODO: (A) implement cross-process links & monitors
-
 // TODO: (B) consider using pub(crate) instead of #[doc(hidden)]
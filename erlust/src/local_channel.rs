@@ -1,33 +1,105 @@
-use futures::channel::mpsc;
+use futures::{channel::mpsc, stream};
 use std::{cell::RefCell, collections::VecDeque};
 
-use crate::{ActorId, LocalReceiver, LocalSender, ReceivedMessage, LOCAL_SENDERS};
+use crate::{
+    call::Calls,
+    signal::Signal,
+    types::{SignalSender, SystemReceiver},
+    ActorId, ExitReason, LocalReceiver, LocalSender, Overflow, Pid, ReceivedMessage, Ref,
+    LOCAL_SENDERS,
+};
 
-const QUEUE_BUFFER: usize = 64;
-// TODO: (C) make QUEUE_BUFFER configurable
-// TODO: (B) limit waiting queue size too
+/// The default capacity of the mailbox, see [`SpawnOptions::mailbox_capacity`]
+pub const QUEUE_BUFFER: usize = 64;
 
 pub struct LocalChannel {
     pub actor_id: ActorId,
     pub sender:   LocalSender,
+    pub signals:  SignalSender,
     pub receiver: LocalReceiver,
     pub waiting:  VecDeque<ReceivedMessage>,
+
+    /// The maximum length of `waiting`, and what to do when it is reached
+    pub waiting_limit: Option<(usize, Overflow)>,
+
+    /// Whether the waiting queue overflowed with [`Overflow::Kill`], in
+    /// which case the actor must not handle any more messages
+    pub killed: bool,
+
+    /// The calls made by the actor, see [`Pid::call`]
+    pub calls: Calls,
+
+    /// Actors monitored by this actor
+    ///
+    /// This is kept here rather than in the [`LocalChannelUpdater`], so that
+    /// a monitor removed by [`Pid::demonitor`] no longer delivers anything
+    /// as soon as `demonitor` is called.
+    ///
+    /// [`LocalChannelUpdater`]: crate::LocalChannelUpdater
+    pub watching: Vec<(Ref, Pid)>,
 }
 
 impl LocalChannel {
-    pub fn new() -> LocalChannel {
-        let (sender, receiver) = mpsc::channel(QUEUE_BUFFER);
+    pub fn new(
+        capacity: usize,
+        waiting_limit: Option<(usize, Overflow)>,
+        signals: SignalSender,
+        system: SystemReceiver,
+    ) -> LocalChannel {
+        let (sender, receiver) = mpsc::channel(capacity);
         // TODO: (A) make async (qutex + change in my task_local handler) h:https://github.com/Amanieu/parking_lot/issues/86
-        let actor_id = LOCAL_SENDERS.write().unwrap().allocate(sender.clone());
+        let actor_id = LOCAL_SENDERS
+            .write()
+            .unwrap()
+            .allocate(sender.clone(), signals.clone());
         LocalChannel {
             actor_id,
             sender,
-            receiver,
+            signals,
+            receiver: stream::select(receiver, system),
             waiting: VecDeque::new(),
+            waiting_limit,
+            killed: false,
+            calls: Calls::default(),
+            watching: Vec::new(),
         }
     }
+
+    /// Adds `msg` at the end of `waiting`, applying the overflow policy if
+    /// it is full
+    pub fn push_waiting(&mut self, msg: ReceivedMessage) {
+        match self.waiting_limit {
+            Some((limit, overflow)) if self.waiting.len() >= limit => match overflow {
+                Overflow::DropOldest => {
+                    self.waiting.pop_front();
+                    self.waiting.push_back(msg);
+                }
+                Overflow::DropNewest => (),
+                Overflow::Kill if !self.killed => {
+                    self.killed = true;
+                    // Ignore errors, as they mean the actor is already terminating
+                    let _ = self
+                        .signals
+                        .unbounded_send(Signal::Terminate(ExitReason::Overflow));
+                }
+                Overflow::Kill => (),
+            },
+            _ => self.waiting.push_back(msg),
+        }
+    }
+}
+
+/// Runs `f` on the channel of the currently running actor
+///
+/// Panics if not called from an actor task.
+pub fn with_my_channel<R>(f: impl FnOnce(&mut LocalChannel) -> R) -> R {
+    MY_CHANNEL.with(|c| {
+        f(c.borrow_mut()
+            .as_mut()
+            .expect("Not called from an actor task"))
+    })
 }
 
 thread_local! {
-    pub static MY_CHANNEL: RefCell<Option<LocalChannel>> = RefCell::new(None);
+    pub static MY_CHANNEL: RefCell<Option<LocalChannel>> = const { RefCell::new(None) };
 }
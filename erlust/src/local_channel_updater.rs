@@ -1,39 +1,300 @@
+use futures::{channel::mpsc, future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
 use std::{
     future::Future,
+    mem,
+    panic::{self, AssertUnwindSafe},
     pin::Pin,
     task::{self, Poll},
 };
 
-use crate::{LocalChannel, MY_CHANNEL};
+use crate::{
+    exit::panic_reason, local_channel::QUEUE_BUFFER, signal::Signal, spawn::spawn_detached,
+    types::SignalReceiver, Down, Exit, ExitReason, LocalChannel, LocalMessage, Overflow, Pid,
+    ReceivedMessage, Ref, SystemSender, LOCAL_SENDERS, MY_CHANNEL,
+};
 
 pub struct LocalChannelUpdater<Fut: Future<Output = ()>> {
     channel: Option<LocalChannel>,
-    fut:     Fut,
+    me:      Pid,
+    signals: SignalReceiver,
+    system:  SystemSender,
+
+    /// Actors linked to this actor
+    links: Vec<Pid>,
+
+    /// Actors monitoring this actor
+    monitors: Vec<(Ref, Pid)>,
+
+    /// Signals being sent to remote actors
+    outgoing: FuturesUnordered<BoxFuture<'static, ()>>,
+
+    trap_exit: bool,
+    polled:    bool,
+    exited:    bool,
+
+    /// The actor itself
+    fut: Fut,
 }
 
 impl<Fut: Future<Output = ()>> LocalChannelUpdater<Fut> {
     pub fn new(fut: Fut) -> LocalChannelUpdater<Fut> {
+        LocalChannelUpdater::with_options(fut, QUEUE_BUFFER, None)
+    }
+
+    /// Builds an updater whose mailbox has capacity `capacity`, and whose
+    /// waiting queue is limited by `waiting_limit`
+    pub fn with_options(
+        fut: Fut,
+        capacity: usize,
+        waiting_limit: Option<(usize, Overflow)>,
+    ) -> LocalChannelUpdater<Fut> {
+        let (signals_sender, signals) = mpsc::unbounded();
+        let (system, system_receiver) = mpsc::unbounded();
+        let channel = LocalChannel::new(
+            capacity,
+            waiting_limit,
+            signals_sender.clone(),
+            system_receiver,
+        );
+        let me = Pid::local(channel.actor_id, channel.sender.clone(), signals_sender);
         LocalChannelUpdater {
-            channel: Some(LocalChannel::new()),
+            channel: Some(channel),
+            me,
+            signals,
+            system,
+            links: Vec::new(),
+            monitors: Vec::new(),
+            outgoing: FuturesUnordered::new(),
+            trap_exit: false,
+            polled: false,
+            exited: false,
             fut,
         }
     }
+
+    /// Returns the [`Pid`] of the actor run by this updater
+    pub fn pid(&self) -> Pid {
+        self.me.clone()
+    }
+
+    /// Sends `signal` to `to`, delaying the actual sending to the next calls
+    /// to `poll_outgoing` if `to` is a remote actor
+    fn send_signal(&mut self, to: Pid, signal: Signal) {
+        if to.is_local() {
+            to.signal_local(signal);
+        } else {
+            self.outgoing.push(
+                async move {
+                    // Ignore errors, as there is no one left to report them to
+                    let _ = to.signal(signal).await;
+                }
+                .boxed(),
+            );
+        }
+    }
+
+    /// Polls the signals being sent to remote actors, returning `Ready` once
+    /// they have all been sent
+    fn poll_outgoing(&mut self, lw: &mut task::Context) -> Poll<()> {
+        while let Poll::Ready(Some(())) = self.outgoing.poll_next_unpin(lw) {}
+        if self.outgoing.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Delivers `msg`, coming from `from`, to the mailbox
+    fn deliver(&mut self, from: Pid, msg: LocalMessage) {
+        // Ignore errors, as they only mean the mailbox was closed
+        let _ = self
+            .system
+            .unbounded_send(ReceivedMessage::Local((from, msg)));
+    }
+
+    /// Handles an exit signal from `pid`, returning `Some` iff it means the
+    /// actor should terminate
+    fn handle_exit(&mut self, pid: Pid, reason: ExitReason) -> Option<ExitReason> {
+        if self.trap_exit {
+            let msg = Box::new(Exit {
+                pid: pid.clone(),
+                reason,
+            });
+            self.deliver(pid, msg);
+            None
+        } else if reason != ExitReason::Normal {
+            Some(reason)
+        } else {
+            None
+        }
+    }
+
+    /// Handles `signal`, returning `Some` iff it means the actor should
+    /// terminate
+    fn handle_signal(&mut self, signal: Signal) -> Option<ExitReason> {
+        match signal {
+            Signal::Link(pid) => {
+                if !self.links.contains(&pid) {
+                    self.links.push(pid);
+                }
+            }
+            Signal::Unlink(pid) => self.links.retain(|p| *p != pid),
+            Signal::TrapExit(flag) => self.trap_exit = flag,
+            Signal::Exit(pid, reason) => {
+                // Ignore exit signals from actors we are not linked to (any longer)
+                let idx = self.links.iter().position(|p| *p == pid)?;
+                self.links.swap_remove(idx);
+                return self.handle_exit(pid, reason);
+            }
+            Signal::Kill(_, ExitReason::Kill) => return Some(ExitReason::Killed),
+            Signal::Kill(pid, reason) => return self.handle_exit(pid, reason),
+            Signal::Terminate(reason) => return Some(reason),
+            Signal::Monitor(monitor_ref, pid) => self.monitors.push((monitor_ref, pid)),
+            Signal::Demonitor(monitor_ref, pid) => self
+                .monitors
+                .retain(|(r, p)| *r != monitor_ref || *p != pid),
+            Signal::Down(down) => {
+                // Ignore down signals for monitors that were removed
+                let watching = &mut self.channel.as_mut()?.watching;
+                let idx = watching
+                    .iter()
+                    .position(|(r, p)| *r == down.monitor_ref && *p == down.pid)?;
+                watching.swap_remove(idx);
+                let pid = down.pid.clone();
+                self.deliver(pid, Box::new(down));
+            }
+            Signal::NoConnection(theater) => {
+                self.monitors.retain(|(_, p)| !p.is_in(&*theater));
+                let lost = match self.channel.as_mut() {
+                    Some(channel) => {
+                        let (lost, watching) = mem::take(&mut channel.watching)
+                            .into_iter()
+                            .partition(|(_, p)| p.is_in(&*theater));
+                        channel.watching = watching;
+                        lost
+                    }
+                    None => Vec::new(),
+                };
+                for (monitor_ref, pid) in lost {
+                    let down = Box::new(Down {
+                        monitor_ref,
+                        pid: pid.clone(),
+                        reason: ExitReason::NoConnection,
+                    });
+                    self.deliver(pid, down);
+                }
+                let (lost, links): (Vec<_>, _) = mem::take(&mut self.links)
+                    .into_iter()
+                    .partition(|p| p.is_in(&*theater));
+                self.links = links;
+                let mut res = None;
+                for pid in lost {
+                    res = res.or(self.handle_exit(pid, ExitReason::NoConnection));
+                }
+                return res;
+            }
+        }
+        None
+    }
+
+    /// Marks the actor as terminated, and notifies linked and monitoring
+    /// actors
+    fn terminate(&mut self, reason: ExitReason) {
+        self.exited = true;
+        self.signals.close();
+        // Actors that tried to link to or monitor us after we stopped handling
+        // signals must still be told we are gone
+        while let Ok(signal) = self.signals.try_recv() {
+            match signal {
+                Signal::Link(pid) => self.links.push(pid),
+                Signal::Monitor(monitor_ref, pid) => self.monitors.push((monitor_ref, pid)),
+                _ => (),
+            }
+        }
+        // Only after closing the signals channel, so that the actor can no
+        // longer be registered
+        LOCAL_SENDERS
+            .write()
+            .unwrap()
+            .deallocate(self.me.actor_id());
+        // Make sends to the actor fail from now on
+        if let Some(channel) = self.channel.as_mut() {
+            channel.receiver.get_mut().0.close();
+            channel.waiting.clear();
+        }
+        for pid in mem::take(&mut self.links) {
+            let signal = Signal::Exit(self.me.clone(), reason.clone());
+            self.send_signal(pid, signal);
+        }
+        for (monitor_ref, pid) in mem::take(&mut self.monitors) {
+            let signal = Signal::Down(Down {
+                monitor_ref,
+                pid: self.me.clone(),
+                reason: reason.clone(),
+            });
+            self.send_signal(pid, signal);
+        }
+    }
 }
 
 impl<Fut: Future<Output = ()>> Future for LocalChannelUpdater<Fut> {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, lw: &mut task::Context) -> Poll<Self::Output> {
-        MY_CHANNEL.with(|my_channel| {
-            // TODO: (B) Check this unsafe is actually safe and comment here on why
+        // TODO: (B) Check this unsafe is actually safe and comment here on why
+        let this = unsafe { Pin::get_unchecked_mut(self) };
+        if this.exited {
+            // Finish notifying remote actors of our termination
+            return this.poll_outgoing(lw);
+        }
+        this.polled = true;
+
+        while let Poll::Ready(Some(signal)) = this.signals.poll_next_unpin(lw) {
+            if let Some(reason) = this.handle_signal(signal) {
+                this.terminate(reason);
+                return this.poll_outgoing(lw);
+            }
+        }
+
+        let res = MY_CHANNEL.with(|my_channel| {
             // TODO: (B) Use scoped-tls?
-            unsafe {
-                let this = Pin::get_unchecked_mut(self);
-                my_channel.replace(this.channel.take());
-                let res = Pin::new_unchecked(&mut this.fut).poll(lw);
-                this.channel = my_channel.replace(None);
-                res
-            }
-        })
+            my_channel.replace(this.channel.take());
+            let fut = unsafe { Pin::new_unchecked(&mut this.fut) };
+            let res = panic::catch_unwind(AssertUnwindSafe(|| fut.poll(lw)));
+            this.channel = my_channel.replace(None);
+            res
+        });
+        match res {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(())) => {
+                this.terminate(ExitReason::Normal);
+                this.poll_outgoing(lw)
+            }
+            Err(payload) => {
+                this.terminate(panic_reason(payload));
+                this.poll_outgoing(lw)
+            }
+        }
+    }
+}
+
+impl<Fut: Future<Output = ()>> Drop for LocalChannelUpdater<Fut> {
+    fn drop(&mut self) {
+        // An actor that was never polled never started, eg. because spawning it
+        // failed, so there is no one to notify
+        if self.polled && !self.exited {
+            self.terminate(ExitReason::Dropped);
+            // Nothing will poll this updater any longer, so finish notifying
+            // remote actors in the background
+            let mut outgoing = mem::take(&mut self.outgoing);
+            if !outgoing.is_empty() {
+                spawn_detached(async move { while outgoing.next().await.is_some() {} });
+            }
+        } else if !self.exited {
+            LOCAL_SENDERS
+                .write()
+                .unwrap()
+                .deallocate(self.me.actor_id());
+        }
     }
 }
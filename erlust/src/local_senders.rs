@@ -1,30 +1,119 @@
-use std::{collections::HashMap, sync::RwLock};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
 
-use crate::{ActorId, LocalSender};
+use crate::{types::SignalSender, ActorId, LocalSender, Pid, RegistryError};
+
+/// The channels of a local actor
+struct Entry {
+    sender:  LocalSender,
+    signals: SignalSender,
+
+    /// The sender used by [`inject`](crate::inject)
+    ///
+    /// All the injections share it, as each clone of a sender can always send
+    /// one message, even if the mailbox is full.
+    injector: Arc<Mutex<LocalSender>>,
+}
 
 pub struct LocalSenders {
-    next_actor_id: ActorId,
-    map: HashMap<ActorId, LocalSender>,
+    map: HashMap<ActorId, Entry>,
+    names: HashMap<String, ActorId>,
+    registered: HashMap<ActorId, String>,
 }
 
 impl LocalSenders {
     fn new() -> LocalSenders {
         LocalSenders {
-            next_actor_id: 0,
             map: HashMap::new(),
+            names: HashMap::new(),
+            registered: HashMap::new(),
         }
     }
 
-    pub fn allocate(&mut self, sender: LocalSender) -> ActorId {
-        let actor_id = self.next_actor_id;
-        // TODO: (C) try to handle gracefully the overflow case
-        self.next_actor_id = self.next_actor_id.checked_add(1).unwrap();
-        self.map.insert(actor_id, sender);
+    /// Registers a new actor, and returns its [`ActorId`]
+    ///
+    /// Actor ids are drawn at random, so that they cannot be guessed by
+    /// remote theaters, and act as capabilities.
+    pub fn allocate(&mut self, sender: LocalSender, signals: SignalSender) -> ActorId {
+        let actor_id = loop {
+            let mut bytes = [0; 16];
+            getrandom::getrandom(&mut bytes).expect("failed to get random bytes");
+            let actor_id = ActorId::from_ne_bytes(bytes);
+            if !self.map.contains_key(&actor_id) {
+                break actor_id;
+            }
+        };
+        let injector = Arc::new(Mutex::new(sender.clone()));
+        self.map.insert(
+            actor_id,
+            Entry {
+                sender,
+                signals,
+                injector,
+            },
+        );
         actor_id
     }
 
-    pub fn get(&self, actor_id: ActorId) -> Option<LocalSender> {
-        self.map.get(&actor_id).map(|s| s.clone())
+    /// Returns the sender [`inject`](crate::inject) should use for
+    /// `actor_id`
+    pub fn injector(&self, actor_id: ActorId) -> Option<Arc<Mutex<LocalSender>>> {
+        self.map.get(&actor_id).map(|e| e.injector.clone())
+    }
+
+    pub fn get_signals(&self, actor_id: ActorId) -> Option<SignalSender> {
+        self.map.get(&actor_id).map(|e| e.signals.clone())
+    }
+
+    /// Returns the [`Pid`] of local actor `actor_id`, if it is known
+    pub fn pid(&self, actor_id: ActorId) -> Option<Pid> {
+        let e = self.map.get(&actor_id)?;
+        Some(Pid::local(actor_id, e.sender.clone(), e.signals.clone()))
+    }
+
+    pub fn register(&mut self, name: &str, actor_id: ActorId) -> Result<(), RegistryError> {
+        match self.map.get(&actor_id) {
+            // The signals channel is closed as soon as the actor terminates,
+            // before `deallocate` is called
+            Some(e) if !e.signals.is_closed() => (),
+            _ => return Err(RegistryError::NoProc),
+        }
+        if self.names.contains_key(name) {
+            return Err(RegistryError::NameTaken);
+        }
+        if self.registered.contains_key(&actor_id) {
+            return Err(RegistryError::AlreadyRegistered);
+        }
+        self.names.insert(String::from(name), actor_id);
+        self.registered.insert(actor_id, String::from(name));
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, name: &str) -> Result<(), RegistryError> {
+        let actor_id = self
+            .names
+            .remove(name)
+            .ok_or(RegistryError::NotRegistered)?;
+        self.registered.remove(&actor_id);
+        Ok(())
+    }
+
+    /// Forgets about `actor_id`, along with the name it is registered under
+    pub fn deallocate(&mut self, actor_id: ActorId) {
+        self.map.remove(&actor_id);
+        if let Some(name) = self.registered.remove(&actor_id) {
+            self.names.remove(&name);
+        }
+    }
+
+    pub fn whereis(&self, name: &str) -> Option<Pid> {
+        self.pid(*self.names.get(name)?)
+    }
+
+    pub fn all_signals(&self) -> impl '_ + Iterator<Item = &SignalSender> {
+        self.map.values().map(|e| &e.signals)
     }
 }
 
@@ -0,0 +1,130 @@
+//! A [`Theater`] simulating multiple theaters in the same process, with the
+//! `loopback` feature
+
+use erased_serde::{Deserializer, Serialize as ErasedSerialize};
+use futures::future::FutureObj;
+use serde::ser::Error as SerdeSerError;
+use std::{collections::HashSet, sync::RwLock};
+
+use crate::{connection_lost, inject, json, ActorId, Error, Message, Theater, TheaterBox};
+
+lazy_static! {
+    /// The pairs of theaters that are currently disconnected, smallest name
+    /// first
+    static ref DISCONNECTED: RwLock<HashSet<(String, String)>> = RwLock::new(HashSet::new());
+}
+
+/// Returns the key of the link between `a` and `b` in [`DISCONNECTED`]
+fn link(a: &str, b: &str) -> (String, String) {
+    match a < b {
+        true => (String::from(a), String::from(b)),
+        false => (String::from(b), String::from(a)),
+    }
+}
+
+/// Theater `there`, as reached from theater `here`, both being simulated in
+/// the local process
+///
+/// All the simulated theaters are connected to each other, and share the
+/// actors of the local process: sending to a [`Pid`](crate::Pid) built with
+/// [`Pid::remote`](crate::Pid::remote) and a `LoopbackTheater` delivers the
+/// message to the local actor with this [`ActorId`], after going through
+/// the whole serialization and [`inject`] path. This is meant for testing
+/// code dealing with multiple theaters.
+///
+/// Messages are encoded as JSON.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoopbackTheater {
+    here:  String,
+    there: String,
+}
+
+impl Message for LoopbackTheater {
+    fn tag() -> &'static str {
+        "erlust::LoopbackTheater"
+    }
+}
+
+impl LoopbackTheater {
+    /// Designates theater `there`, as reached from theater `here`
+    pub fn new(here: &str, there: &str) -> LoopbackTheater {
+        LoopbackTheater {
+            here:  String::from(here),
+            there: String::from(there),
+        }
+    }
+
+    /// Simulates the loss of the connection between theaters `a` and `b`
+    ///
+    /// Local actors linked to or monitoring actors through this connection
+    /// are notified with [`ExitReason::NoConnection`](crate::ExitReason),
+    /// and sending through it fails until [`LoopbackTheater::reconnect`] is
+    /// called.
+    pub fn disconnect(a: &str, b: &str) {
+        DISCONNECTED.write().unwrap().insert(link(a, b));
+        connection_lost(&LoopbackTheater::new(a, b));
+        connection_lost(&LoopbackTheater::new(b, a));
+    }
+
+    /// Restores the connection between theaters `a` and `b`
+    pub fn reconnect(a: &str, b: &str) {
+        DISCONNECTED.write().unwrap().remove(&link(a, b));
+    }
+}
+
+impl Theater for LoopbackTheater {
+    fn here(&mut self) -> Result<Box<Self>, Error> {
+        Ok(Box::new(LoopbackTheater::new(&self.there, &self.here)))
+    }
+
+    fn sees_as(&mut self, other: Box<dyn TheaterBox>) -> Result<Box<Self>, Error> {
+        let other = other
+            .into_any()
+            .downcast::<LoopbackTheater>()
+            .map_err(|_| {
+                Error::Serialization(SerdeSerError::custom(
+                    "LoopbackTheater can only reach other LoopbackTheaters",
+                ))
+            })?;
+        Ok(Box::new(LoopbackTheater::new(&self.here, &other.there)))
+    }
+
+    fn encode(
+        &mut self,
+        msg: &dyn ErasedSerialize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), erased_serde::Error> {
+        json::encode(msg, out)
+    }
+
+    fn decode(
+        &mut self,
+        inp: &[u8],
+        visit: &mut dyn FnMut(&mut dyn Deserializer) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), erased_serde::Error> {
+        json::decode(inp, visit)
+    }
+
+    fn send(
+        &mut self,
+        from: ActorId,
+        to: ActorId,
+        tag: &'static str,
+        msg: Vec<u8>,
+    ) -> FutureObj<'_, Result<(), Error>> {
+        FutureObj::new(Box::pin(async move {
+            if DISCONNECTED
+                .read()
+                .unwrap()
+                .contains(&link(&self.here, &self.there))
+            {
+                return Err(Error::transport("theaters are disconnected"));
+            }
+            // The receiving theater reaches the sending one from `there`
+            let from_theater = Box::new(LoopbackTheater::new(&self.there, &self.here));
+            inject(from, to, String::from(tag), msg, from_theater).await?;
+            Ok(())
+        }))
+    }
+}
@@ -0,0 +1,72 @@
+//! Monitoring of the termination of actors
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    receive::{downcast_if, receive_timeout},
+    ExitReason, Message, Pid, ReceiveResult, ReceivedMessage,
+};
+
+/// A reference, unique in the local theater
+///
+/// Used to identify monitors (see [`Pid::monitor`]).
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Ref(u64);
+
+static NEXT_REF: AtomicU64 = AtomicU64::new(0);
+
+impl Ref {
+    /// Generates a new reference, different from all the previous ones
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Ref {
+        Ref(NEXT_REF.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Message received by an actor when an actor it monitors terminates
+///
+/// See [`Pid::monitor`].
+#[derive(Deserialize, Serialize)]
+pub struct Down {
+    /// The reference returned by [`Pid::monitor`] when setting up the monitor
+    pub monitor_ref: Ref,
+
+    /// The actor that terminated
+    pub pid: Pid,
+
+    /// The reason why it terminated
+    pub reason: ExitReason,
+}
+
+impl Message for Down {
+    fn tag() -> &'static str {
+        "erlust::Down"
+    }
+}
+
+/// Removes the monitor `monitor_ref` on `pid`, along with the [`Down`]
+/// message it may already have delivered
+///
+/// Panics if not called from an actor task.
+pub(crate) async fn demonitor_flush(pid: &Pid, monitor_ref: Ref) {
+    pid.demonitor(monitor_ref).await;
+    // No `Down` can be delivered after `demonitor`, so those already
+    // delivered are all either waiting or ready to be received
+    receive_timeout(
+        async move |msg: &mut Option<ReceivedMessage>| {
+            let m = msg.take().unwrap();
+            match downcast_if(m, |down: &Down| down.monitor_ref == monitor_ref) {
+                Ok(_) => ReceiveResult::Use(()),
+                Err(m) => {
+                    *msg = Some(m);
+                    ReceiveResult::Skip
+                }
+            }
+        },
+        Duration::from_secs(0),
+    )
+    .await;
+}
@@ -1,10 +1,14 @@
 //! Ways to transparently send messages to actors both locally and remotely
 
-use erased_serde::Serialize as ErasedSerdeSerialize;
 use futures::{SinkExt, TryFutureExt};
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::{ser::Error as SerdeSerError, Deserialize, Deserializer, Serialize, Serializer};
+use std::time::Duration;
 
-use crate::{ActorId, LocalSender, Message, ReceivedMessage, TheaterBox, HERE, MY_CHANNEL};
+use crate::{
+    call, local_channel::with_my_channel, signal::Signal, types::SignalSender, ActorId, CallError,
+    Down, Error, ExitReason, LocalSender, Message, ReceivedMessage, Ref, Theater, TheaterBox, HERE,
+    MY_CHANNEL,
+};
 
 /// The address of an actor, used to send it messages
 pub struct Pid(PidImpl);
@@ -28,6 +32,9 @@ struct LocalPid {
 
     /// The local sender for local usage
     sender: LocalSender,
+
+    /// The sender for signals to the actor
+    signals: SignalSender,
 }
 
 /// A [`Pid`] for an actor in a potentially remote theater
@@ -52,12 +59,21 @@ impl Pid {
     ///
     /// Panics if not called from an actor task.
     pub fn me() -> Pid {
-        let (actor_id, sender) = MY_CHANNEL.with(|c| {
+        let (actor_id, sender, signals) = MY_CHANNEL.with(|c| {
             let cell = c.borrow();
             let chan = cell.as_ref().unwrap();
-            (chan.actor_id, chan.sender.clone())
+            (chan.actor_id, chan.sender.clone(), chan.signals.clone())
         });
-        Pid(PidImpl::Local(LocalPid { actor_id, sender }))
+        Pid::local(actor_id, sender, signals)
+    }
+
+    /// Builder for a local actor from its raw parts
+    pub(crate) fn local(actor_id: ActorId, sender: LocalSender, signals: SignalSender) -> Pid {
+        Pid(PidImpl::Local(LocalPid {
+            actor_id,
+            sender,
+            signals,
+        }))
     }
 
     /// Builder for a remote actor from its raw parts
@@ -78,19 +94,199 @@ impl Pid {
         }
     }
 
+    /// Builds the address of actor `actor_id` of `theater`
+    ///
+    /// This is useful for bootstrapping communication with a remote theater,
+    /// when the [`ActorId`] of one of its actors was obtained out-of-band.
+    pub fn remote<T: Theater>(actor_id: ActorId, theater: T) -> Pid {
+        Pid::__remote(actor_id, Box::new(theater))
+    }
+
+    /// Returns the [`ActorId`] of `self` in the theater it lives in
+    pub fn actor_id(&self) -> ActorId {
+        match self.0 {
+            PidImpl::Local(ref l) => l.actor_id,
+            PidImpl::Remote(ref r) => r.actor_id,
+        }
+    }
+
+    /// Checks whether `self` is an actor of the local theater
+    pub(crate) fn is_local(&self) -> bool {
+        matches!(self.0, PidImpl::Local(_))
+    }
+
+    /// Checks whether `self` is an actor of `theater`
+    pub(crate) fn is_in(&self, theater: &dyn TheaterBox) -> bool {
+        match self.0 {
+            PidImpl::Local(_) => false,
+            PidImpl::Remote(ref r) => r.theater.eq_box(theater),
+        }
+    }
+
+    /// Sends `signal` to `self`, that must be a local actor
+    ///
+    /// Returns `false` if `self` is known to have already terminated.
+    ///
+    /// Panics if `self` is not a local actor.
+    pub(crate) fn signal_local(&self, signal: Signal) -> bool {
+        match self.0 {
+            PidImpl::Local(ref l) => l.signals.unbounded_send(signal).is_ok(),
+            PidImpl::Remote(_) => unreachable!(),
+        }
+    }
+
+    /// Sends `signal` to `self`
+    ///
+    /// Fails with the reason to report if `self` is known to be unreachable,
+    /// ie. [`ExitReason::NoProc`] if it has already terminated and
+    /// [`ExitReason::NoConnection`] if its theater could not be reached.
+    pub(crate) async fn signal(&self, signal: Signal) -> Result<(), ExitReason> {
+        match self.0 {
+            PidImpl::Local(_) => match self.signal_local(signal) {
+                true => Ok(()),
+                false => Err(ExitReason::NoProc),
+            },
+            PidImpl::Remote(ref r) => {
+                let (from, signal) = match signal.into_remote() {
+                    Some(s) => s,
+                    None => return Ok(()),
+                };
+                let mut theater = r.theater.clone_to_box();
+                let mut vec = Vec::with_capacity(32);
+                theater
+                    .encode(&signal, &mut vec)
+                    .map_err(|_| ExitReason::NoConnection)?;
+                theater
+                    .send_signal(from, r.actor_id, vec)
+                    .await
+                    .map_err(|_| ExitReason::NoConnection)
+            }
+        }
+    }
+
+    /// Links the currently running actor to `self`
+    ///
+    /// When either of the two actors terminates, the other one will receive
+    /// an exit signal, that will make it terminate too unless the exit was
+    /// normal or it traps exits (see [`trap_exit`](crate::trap_exit)).
+    ///
+    /// If `self` has already terminated, the currently running actor
+    /// receives an exit signal with reason [`ExitReason::NoProc`]. If `self`
+    /// is located in a remote theater and the connection to it is lost, the
+    /// exit signal has reason [`ExitReason::NoConnection`]. Linking an actor
+    /// to itself or linking twice the same actors has no effect.
+    ///
+    /// Panics if not called from an actor task.
+    pub async fn link(&self) {
+        let me = Pid::me();
+        if *self == me {
+            return;
+        }
+        me.signal_local(Signal::Link(self.clone()));
+        if let Err(reason) = self.signal(Signal::Link(me.clone())).await {
+            me.signal_local(Signal::Exit(self.clone(), reason));
+        }
+    }
+
+    /// Removes the link between the currently running actor and `self`
+    ///
+    /// Exit signals already received by the currently running actor before
+    /// the call to `unlink` will still be handled.
+    ///
+    /// Panics if not called from an actor task.
+    pub async fn unlink(&self) {
+        let me = Pid::me();
+        me.signal_local(Signal::Unlink(self.clone()));
+        // Ignore errors, as the link is gone anyway if `self` is unreachable
+        let _ = self.signal(Signal::Unlink(me)).await;
+    }
+
+    /// Asks `self` to terminate with reason `reason`
+    ///
+    /// If `self` traps exits, it receives an [`Exit`](crate::Exit) message
+    /// instead, whose `pid` is the currently running actor. Otherwise, it
+    /// terminates with reason `reason`, unless said reason is
+    /// [`ExitReason::Normal`].
+    ///
+    /// If `reason` is [`ExitReason::Kill`], `self` terminates even if it traps
+    /// exits, with reason [`ExitReason::Killed`].
+    ///
+    /// Panics if not called from an actor task.
+    pub async fn exit(&self, reason: ExitReason) {
+        // Ignore errors, as it means `self` already terminated
+        let _ = self.signal(Signal::Kill(Pid::me(), reason)).await;
+    }
+
+    /// Makes the currently running actor monitor `self`
+    ///
+    /// When `self` terminates, the currently running actor will receive a
+    /// [`Down`] message, whose `monitor_ref` is the [`Ref`] returned by this
+    /// function. If `self` has already terminated, this message is received
+    /// immediately, with reason [`ExitReason::NoProc`]. If `self` is located
+    /// in a remote theater and the connection to it is lost, this message is
+    /// received with reason [`ExitReason::NoConnection`].
+    ///
+    /// Contrary to links, monitors are unidirectional, and each call to
+    /// `monitor` sets up a new monitor.
+    ///
+    /// Panics if not called from an actor task.
+    pub async fn monitor(&self) -> Ref {
+        let me = Pid::me();
+        let monitor_ref = Ref::new();
+        with_my_channel(|chan| chan.watching.push((monitor_ref, self.clone())));
+        if let Err(reason) = self.signal(Signal::Monitor(monitor_ref, me.clone())).await {
+            me.signal_local(Signal::Down(Down {
+                monitor_ref,
+                pid: self.clone(),
+                reason,
+            }));
+        }
+        monitor_ref
+    }
+
+    /// Removes the monitor identified by `monitor_ref` from `self`
+    ///
+    /// No [`Down`] message for this monitor is received after this call.
+    /// However, one may already be in the mailbox of the currently running
+    /// actor, if `self` terminated before the call to `demonitor`.
+    ///
+    /// Panics if not called from an actor task.
+    pub async fn demonitor(&self, monitor_ref: Ref) {
+        let me = Pid::me();
+        with_my_channel(|chan| chan.watching.retain(|(r, _)| *r != monitor_ref));
+        // Ignore errors, as the monitor is gone anyway if `self` is unreachable
+        let _ = self.signal(Signal::Demonitor(monitor_ref, me)).await;
+    }
+
+    /// Sends `request` to `self`, and waits for its answer for at most
+    /// `timeout`
+    ///
+    /// `self` receives a [`Call<Req>`](crate::Call), and answers with
+    /// [`Caller::reply`](crate::Caller::reply). `self` is monitored during
+    /// the call, so that the call fails as soon as it terminates. Answers
+    /// arriving after the call timed out are dropped.
+    ///
+    /// Panics if not called from an actor task.
+    pub async fn call<Req, Resp>(&self, request: Req, timeout: Duration) -> Result<Resp, CallError>
+    where
+        Req: Message,
+        Resp: Message,
+    {
+        call::call(self, request, timeout).await
+    }
+
     /// Sends `msg` to `self`
     ///
     /// Fails if the message could not be sent. Please remember that depending
     /// on the [`Theater`] implementation, some messages may still be lost
     /// in transit, even if this function did not return `Err` to the
     /// caller.
-    // TODO: (B) either replace failure::Error by a better type or document why not
-    pub async fn send<M: Message>(&mut self, msg: Box<M>) -> Result<(), failure::Error> {
+    pub async fn send<M: Message>(&mut self, msg: Box<M>) -> Result<(), Error> {
         match self.0 {
             PidImpl::Local(ref mut l) => {
                 l.sender
                     .send(ReceivedMessage::Local((Pid::me(), msg)))
-                    .map_err(|e| e.into())
+                    .map_err(Error::LocalSend)
                     .await
             }
             PidImpl::Remote(ref mut r) => {
@@ -98,13 +294,11 @@ impl Pid {
                 // Note: if erased_serialize can yield, will have to replace the thread_local
                 // usage with a task_local one.
                 let mut vec = Vec::with_capacity(128);
-                let mut erased_ser = r.theater.serializer(&mut vec);
-                HERE.with(|here| -> Result<(), erased_serde::Error> {
-                    *here.borrow_mut() = Some(r.theater.here());
-                    msg.erased_serialize(&mut erased_ser)?;
-                    *here.borrow_mut() = None;
-                    Ok(())
-                })?;
+                let here = r.theater.here()?;
+                HERE.with(|h| *h.borrow_mut() = Some(here));
+                let res = r.theater.encode(&*msg, &mut vec);
+                HERE.with(|h| *h.borrow_mut() = None);
+                res?;
                 r.theater
                     .send(my_actor_id(), r.actor_id, M::tag(), vec)
                     .await
@@ -113,6 +307,29 @@ impl Pid {
     }
 }
 
+impl Clone for Pid {
+    fn clone(&self) -> Pid {
+        match self.0 {
+            PidImpl::Local(ref l) => Pid::local(l.actor_id, l.sender.clone(), l.signals.clone()),
+            PidImpl::Remote(ref r) => Pid::__remote(r.actor_id, r.theater.clone_to_box()),
+        }
+    }
+}
+
+impl PartialEq for Pid {
+    fn eq(&self, other: &Pid) -> bool {
+        match (&self.0, &other.0) {
+            (PidImpl::Local(a), PidImpl::Local(b)) => a.actor_id == b.actor_id,
+            (PidImpl::Remote(a), PidImpl::Remote(b)) => {
+                a.actor_id == b.actor_id && a.theater.eq_box(&*b.theater)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Pid {}
+
 impl Serialize for Pid {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -130,7 +347,9 @@ impl Serialize for Pid {
             PidImpl::Remote(ref r) => {
                 let seen_from_remote = RemotePid {
                     actor_id: r.actor_id,
-                    theater:  here.sees_as(r.theater.clone_to_box()),
+                    theater:  here
+                        .sees_as(r.theater.clone_to_box())
+                        .map_err(S::Error::custom)?,
                 };
                 seen_from_remote.serialize(serializer)
             }
@@ -1,69 +1,239 @@
-use futures::{Future, StreamExt};
-use std::mem;
+use futures::{
+    future::{self, Either},
+    StreamExt,
+};
+use serde::de::Error as SerdeDeError;
+use std::time::Duration;
 
-use crate::{LocalMessage, Pid, ReceivedMessage, MY_CHANNEL};
+use crate::{
+    call::is_late_reply, local_channel::with_my_channel, timer::Delay, ActorId, Error, Message,
+    Pid, ReceivedMessage, HERE, MY_CHANNEL,
+};
 
+/// Deserializes `msg`, received from remote actor `from`, into an `M`
+///
+/// [`Pid`]s contained in `msg` are deserialized as actors of the theater of
+/// `from`.
+///
+/// Panics if `from` is not a remote actor.
+#[doc(hidden)]
+pub fn __deserialize_remote<M: Message>(from: &Pid, msg: &[u8]) -> Result<Box<M>, Error> {
+    let mut theater = from.__theater_assert_remote();
+    let here = theater.here()?;
+    let previous = HERE.with(|h| h.replace(Some(here)));
+    let mut res = None;
+    let status = theater.decode(msg, &mut |d| {
+        res = Some(erased_serde::deserialize::<Box<M>>(d)?);
+        Ok(())
+    });
+    HERE.with(|h| *h.borrow_mut() = previous);
+    status?;
+    res.ok_or_else(|| {
+        Error::Serialization(SerdeDeError::custom("Theater::decode did not call visit"))
+    })
+}
+
+/// Extracts `msg` as an `M`, giving it back if it is of another type
+pub(crate) fn downcast<M: Message>(msg: ReceivedMessage) -> Result<(Pid, Box<M>), ReceivedMessage> {
+    downcast_if(msg, |_: &M| true)
+}
+
+/// Extracts `msg` as an `M` accepted by `accept`, giving it back untouched
+/// if it is of another type or not accepted
+pub(crate) fn downcast_if<M, F>(
+    msg: ReceivedMessage,
+    accept: F,
+) -> Result<(Pid, Box<M>), ReceivedMessage>
+where
+    M: Message,
+    F: FnOnce(&M) -> bool,
+{
+    match msg {
+        ReceivedMessage::Local((from, msg)) => {
+            match msg.as_any().downcast_ref::<M>().is_some_and(accept) {
+                true => Ok((from, msg.into_any().downcast::<M>().unwrap())),
+                false => Err(ReceivedMessage::Local((from, msg))),
+            }
+        }
+        ReceivedMessage::Remote((from, m)) => {
+            if m.tag == M::tag() {
+                if let Ok(msg) = __deserialize_remote::<M>(&from, &m.msg) {
+                    if accept(&msg) {
+                        return Ok((from, msg));
+                    }
+                }
+            }
+            Err(ReceivedMessage::Remote((from, m)))
+        }
+    }
+}
+
+/// What a handler passed to [`receive`] did with the message it was given
 pub enum ReceiveResult<Ret> {
+    /// The message was accepted, and `receive` should return `Ret`
     Use(Ret),
-    Skip(ReceivedMessage),
+
+    /// The message was not accepted, and should be kept in the mailbox if it
+    /// was left in place
+    Skip,
 }
 
-// Note: it's highly recommended to use by setting a timeout on the returned
-// future
-pub async fn receive<HandleFn, Fut, Ret>(handle: HandleFn) -> Ret
+/// A message being handled, that is put back in the waiting list unless it
+/// was used
+///
+/// This makes sure no message is lost if the future returned by [`receive`]
+/// is dropped while the message is being handled.
+struct Handling {
+    actor_id: ActorId,
+
+    /// Where to put the message back in the waiting list, `None` meaning at
+    /// the end
+    index: Option<usize>,
+
+    msg: Option<ReceivedMessage>,
+}
+
+impl Drop for Handling {
+    fn drop(&mut self) {
+        let msg = match self.msg.take() {
+            Some(msg) => msg,
+            None => return,
+        };
+        MY_CHANNEL.with(|c| {
+            // If the channel is not there, then the whole actor is being
+            // dropped, and so is its mailbox
+            let mut cell = match c.try_borrow_mut() {
+                Ok(cell) => cell,
+                Err(_) => return,
+            };
+            if let Some(chan) = cell.as_mut().filter(|c| c.actor_id == self.actor_id) {
+                match self.index {
+                    Some(i) => chan.waiting.insert(i.min(chan.waiting.len()), msg),
+                    None => chan.push_waiting(msg),
+                }
+            }
+        });
+    }
+}
+
+/// Waits for a message `handle` accepts, and returns the result `handle`
+/// gave for it
+///
+/// `handle` is always given `Some` message. It can take the message out of
+/// the `Option` to use it. Messages left in place by a handler that returns
+/// [`ReceiveResult::Skip`] are kept in the mailbox, in order, and will be
+/// handled by the next calls to `receive`.
+///
+/// The returned future can be dropped at any point without losing messages:
+/// if a message is being handled, it is put back in the mailbox, unless it
+/// has already been taken out of the `Option`.
+///
+/// Note that the returned future is only `Send` if `handle` is an `async move`
+/// closure, due to current limitations of the compiler.
+///
+/// Panics if not called from an actor task.
+pub async fn receive<HandleFn, Ret>(handle: HandleFn) -> Ret
+where
+    HandleFn: AsyncFn(&mut Option<ReceivedMessage>) -> ReceiveResult<Ret>,
+{
+    receive_impl(handle, None)
+        .await
+        .expect("receive without a timeout timed out")
+}
+
+/// Same as [`receive`], but gives up after `timeout`
+///
+/// Returns `None` if no message was accepted by `handle` before `timeout`
+/// elapsed. Messages received in the meantime are all kept in the mailbox.
+pub async fn receive_timeout<HandleFn, Ret>(handle: HandleFn, timeout: Duration) -> Option<Ret>
+where
+    HandleFn: AsyncFn(&mut Option<ReceivedMessage>) -> ReceiveResult<Ret>,
+{
+    receive_impl(handle, Some(timeout)).await
+}
+
+async fn receive_impl<HandleFn, Ret>(handle: HandleFn, timeout: Option<Duration>) -> Option<Ret>
 where
-    Fut: Future<Output = ReceiveResult<Ret>>,
-    HandleFn: Fn(ReceivedMessage) -> Fut,
+    HandleFn: AsyncFn(&mut Option<ReceivedMessage>) -> ReceiveResult<Ret>,
 {
-    use self::ReceiveResult::*;
-
-    // This `expect` shouldn't trigger, because `LocalChannelUpdater` should always
-    // keep `MY_CHANNEL` task-local. As such, the only moment where it should be
-    // set to `None` is here, and it is restored before the end of this function,
-    // and `__receive` cannot be called inside `__receive`.
-    let mut chan = MY_CHANNEL
-        .with(|c| c.borrow_mut().take())
-        .expect("Called receive inside receive");
-
-    // First, attempt to find a message in waiting list
-    // Use a temporary variable to work around https://github.com/rust-lang/rust/issues/59245
-    let l = chan.waiting.len();
-    for i in 0..l {
-        // TODO: (C) consider unsafe here to remove the temp. var., dep. on benchmarks
-        let mut msg = ReceivedMessage::Local((Pid::me(), Box::new(()) as LocalMessage));
-        mem::swap(&mut msg, &mut chan.waiting[i]);
-        match handle(msg).await {
-            Use(ret) => {
-                chan.waiting.remove(i);
-                MY_CHANNEL.with(|c| *c.borrow_mut() = Some(chan));
-                return ret;
+    // The timer starts before looking at the waiting list, so that a zero
+    // timeout still handles the messages already received
+    let mut delay = timeout.map(Delay::new);
+    let actor_id = with_my_channel(|chan| chan.actor_id);
+    stop_if_killed().await;
+
+    // First, attempt to find a message in waiting list. The channel is never
+    // borrowed across an `await`, so that `handle` can use it, eg. through
+    // `Pid::me`.
+    let mut remaining = with_my_channel(|chan| chan.waiting.len());
+    let mut i = 0;
+    while remaining > 0 {
+        remaining -= 1;
+        let msg = match with_my_channel(|chan| chan.waiting.remove(i)) {
+            Some(msg) => msg,
+            None => break,
+        };
+        let mut handling = Handling {
+            actor_id,
+            index: Some(i),
+            msg: Some(msg),
+        };
+        match handle(&mut handling.msg).await {
+            ReceiveResult::Use(ret) => {
+                handling.msg = None;
+                return Some(ret);
             }
-            Skip(msg) => {
-                chan.waiting[i] = msg;
+            ReceiveResult::Skip => {
+                if handling.msg.is_some() {
+                    i += 1;
+                }
             }
         }
     }
 
     // Push all irrelevant messages to the waiting list, then return relevant one
     loop {
+        let next = future::poll_fn(|cx| with_my_channel(|chan| chan.receiver.poll_next_unpin(cx)));
+        let next = match delay {
+            Some(ref mut delay) => match future::select(next, delay).await {
+                Either::Left((next, _)) => next,
+                Either::Right(((), _)) => return None,
+            },
+            None => next.await,
+        };
         // This `expect` shouldn't trigger, because `chan.receiver.next()` is
         // supposed to answer `None` iff all `Sender`s associated to the channel
-        // have been dropped. Except we always keep a `Sender` alive in the
-        // `LOCAL_SENDERS` map, and `__receive` should not be able to be called
-        // once the actor has been dropped, so this should be safe.
-        let msg = chan
-            .receiver
-            .next()
-            .await
-            .expect("Called receive after the actor was dropped");
-        match handle(msg).await {
-            Use(ret) => {
-                MY_CHANNEL.with(|c| *c.borrow_mut() = Some(chan));
-                return ret;
-            }
-            Skip(msg) => {
-                chan.waiting.push_back(msg);
-            }
+        // have been dropped or the channel was closed. Except the channel
+        // always keeps a `Sender` to itself, and is only closed once the actor
+        // has terminated, at which point `receive` can no longer be called.
+        let msg = next.expect("Called receive after the actor was dropped");
+        if with_my_channel(|chan| is_late_reply(chan, &msg)) {
+            continue;
         }
+        let mut handling = Handling {
+            actor_id,
+            index: None,
+            msg: Some(msg),
+        };
+        if let ReceiveResult::Use(ret) = handle(&mut handling.msg).await {
+            handling.msg = None;
+            return Some(ret);
+        }
+        // Put the message back now, so that an overflow stops the actor
+        // before it handles the next message
+        drop(handling);
+        stop_if_killed().await;
+    }
+}
+
+/// Never returns if the waiting queue overflowed with [`Overflow::Kill`]
+///
+/// The actor is then terminated by the `Terminate` signal sent on overflow,
+/// which also wakes it up.
+///
+/// [`Overflow::Kill`]: crate::Overflow::Kill
+async fn stop_if_killed() {
+    if with_my_channel(|chan| chan.killed) {
+        future::pending::<()>().await;
     }
 }
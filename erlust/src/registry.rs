@@ -0,0 +1,83 @@
+//! Registry of the names of local actors
+
+use std::{error, fmt};
+
+use crate::{Error, Message, Pid, LOCAL_SENDERS};
+
+/// Error returned by the operations of the local registry
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum RegistryError {
+    /// The name is already used by another actor
+    NameTaken,
+
+    /// The actor is already registered under another name
+    AlreadyRegistered,
+
+    /// The actor is not a local actor, or the name was not registered from
+    /// the local theater
+    NotLocal,
+
+    /// The actor has already terminated
+    NoProc,
+
+    /// No actor is registered under this name
+    NotRegistered,
+
+    /// The [`GlobalRegistry`](crate::global::GlobalRegistry) is not running
+    /// in the local theater
+    NotStarted,
+
+    /// The [`GlobalRegistry`](crate::global::GlobalRegistry) did not answer
+    /// in time
+    Timeout,
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RegistryError::NameTaken => "name already registered",
+            RegistryError::AlreadyRegistered => "actor already registered under another name",
+            RegistryError::NotLocal => "not a local actor",
+            RegistryError::NoProc => "actor already terminated",
+            RegistryError::NotRegistered => "name not registered",
+            RegistryError::NotStarted => "global registry not started",
+            RegistryError::Timeout => "global registry did not answer in time",
+        })
+    }
+}
+
+impl error::Error for RegistryError {}
+
+/// Registers `pid` under `name` in the local theater
+///
+/// Each name can be used by at most one actor, and each actor can be
+/// registered under at most one name. The name is automatically unregistered
+/// when the actor terminates.
+pub fn register(name: &str, pid: &Pid) -> Result<(), RegistryError> {
+    if !pid.is_local() {
+        return Err(RegistryError::NotLocal);
+    }
+    LOCAL_SENDERS
+        .write()
+        .unwrap()
+        .register(name, pid.actor_id())
+}
+
+/// Removes the registration of `name`
+pub fn unregister(name: &str) -> Result<(), RegistryError> {
+    LOCAL_SENDERS.write().unwrap().unregister(name)
+}
+
+/// Returns the actor registered under `name`, if any
+pub fn whereis(name: &str) -> Option<Pid> {
+    LOCAL_SENDERS.read().unwrap().whereis(name)
+}
+
+/// Sends `msg` to the actor registered under `name`
+///
+/// Fails with [`RegistryError::NotRegistered`] if no actor is registered
+/// under `name`, and otherwise like [`Pid::send`].
+pub async fn send_named<M: Message>(name: &str, msg: Box<M>) -> Result<(), Error> {
+    let mut pid = whereis(name).ok_or(RegistryError::NotRegistered)?;
+    pid.send(msg).await
+}
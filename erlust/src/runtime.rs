@@ -0,0 +1,82 @@
+//! Integration with the tokio runtime, with the `tokio` feature
+
+use futures::{
+    channel::oneshot,
+    future::FutureObj,
+    task::{Spawn, SpawnError},
+    Future,
+};
+use tokio::runtime::{Builder, Handle};
+
+use crate::{set_executor, spawn, Error, LocalChannelUpdater, Pid};
+
+/// A spawner for running actors on a tokio runtime
+#[derive(Clone, Debug)]
+pub struct TokioSpawner(Handle);
+
+impl TokioSpawner {
+    /// Builds a spawner for the runtime designated by `handle`
+    pub fn new(handle: Handle) -> TokioSpawner {
+        TokioSpawner(handle)
+    }
+
+    /// Builds a spawner for the runtime the caller is running in
+    ///
+    /// Panics if not called from a tokio runtime.
+    pub fn current() -> TokioSpawner {
+        TokioSpawner(Handle::current())
+    }
+}
+
+impl Spawn for TokioSpawner {
+    fn spawn_obj(&self, future: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+        self.0.spawn(future);
+        Ok(())
+    }
+}
+
+/// Spawns `fut` as a new actor on a thread of the blocking pool of the tokio
+/// runtime the caller is running in, and returns its [`Pid`]
+///
+/// This is meant for actors doing CPU-bound work, that would otherwise
+/// prevent other actors from running. The actor behaves like any other
+/// actor, but has a thread for itself until it terminates.
+///
+/// Fails if not called from a tokio runtime.
+pub fn spawn_blocking<Fut>(fut: Fut) -> Result<Pid, Error>
+where
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let handle = Handle::try_current().map_err(|_| Error::Spawn(SpawnError::shutdown()))?;
+    let task = LocalChannelUpdater::new(fut);
+    let pid = task.pid();
+    handle.spawn_blocking(move || futures::executor::block_on(task));
+    Ok(pid)
+}
+
+/// Runs `fut` as the root actor of a new tokio runtime, that is also set as
+/// the default executor, and returns its result
+///
+/// Used by `#[erlust::main]`. Panics if the root actor terminates without
+/// returning.
+#[doc(hidden)]
+pub fn __run_main<Fut, T>(fut: Fut) -> T
+where
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let runtime = Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build the tokio runtime");
+    set_executor(TokioSpawner::new(runtime.handle().clone()));
+    let (sender, receiver) = oneshot::channel();
+    let _guard = runtime.enter();
+    spawn(async move {
+        let _ = sender.send(fut.await);
+    })
+    .expect("failed to spawn the root actor");
+    runtime
+        .block_on(receiver)
+        .expect("the root actor terminated without returning")
+}
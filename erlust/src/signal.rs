@@ -0,0 +1,105 @@
+//! Out-of-band signals exchanged between actors
+
+use crate::{ActorId, Down, ExitReason, Pid, Ref, TheaterBox};
+
+/// The tag used by [`Theater::send_signal`](crate::Theater::send_signal) by
+/// default, that [`inject`](crate::inject) recognizes as a signal
+pub const SIGNAL_TAG: &str = "erlust::Signal";
+
+/// A signal sent to an actor
+///
+/// Signals are not messages: they are handled by the [`LocalChannelUpdater`]
+/// of the receiving actor, in the order in which they were sent, before the
+/// actor's future is polled. They are never subject to backpressure.
+///
+/// [`LocalChannelUpdater`]: crate::LocalChannelUpdater
+pub enum Signal {
+    /// `Pid` has linked itself to the receiving actor
+    Link(Pid),
+
+    /// `Pid` has removed its link to the receiving actor
+    Unlink(Pid),
+
+    /// The receiving actor wants to start or stop trapping exits
+    TrapExit(bool),
+
+    /// `Pid` has terminated for the given reason
+    Exit(Pid, ExitReason),
+
+    /// `Pid` asks the receiving actor to terminate for the given reason
+    Kill(Pid, ExitReason),
+
+    /// The receiving actor wants to terminate for the given reason
+    Terminate(ExitReason),
+
+    /// `Pid` wants to be notified when the receiving actor terminates
+    Monitor(Ref, Pid),
+
+    /// `Pid` no longer wants to be notified for the monitor identified by
+    /// this [`Ref`]
+    Demonitor(Ref, Pid),
+
+    /// An actor monitored by the receiving actor has terminated
+    Down(Down),
+
+    /// The connection to this theater has been lost
+    NoConnection(Box<dyn TheaterBox>),
+}
+
+/// A [`Signal`] as it is sent across theaters
+///
+/// The [`Pid`] that is the source of the signal is not part of it, as it is
+/// identified by the `from` [`ActorId`] and theater passed to
+/// [`inject`](crate::inject).
+#[derive(Deserialize, Serialize)]
+pub enum RemoteSignal {
+    Link,
+    Unlink,
+    Exit(ExitReason),
+    Kill(ExitReason),
+    Monitor(Ref),
+    Demonitor(Ref),
+    Down(Ref, ExitReason),
+}
+
+impl Signal {
+    /// Splits `self` into the local [`ActorId`] it comes from and the
+    /// [`RemoteSignal`] to send
+    ///
+    /// Returns `None` for signals that only make sense locally.
+    pub fn into_remote(self) -> Option<(ActorId, RemoteSignal)> {
+        match self {
+            Signal::Link(from) => Some((from.actor_id(), RemoteSignal::Link)),
+            Signal::Unlink(from) => Some((from.actor_id(), RemoteSignal::Unlink)),
+            Signal::Exit(from, reason) => Some((from.actor_id(), RemoteSignal::Exit(reason))),
+            Signal::Kill(from, reason) => Some((from.actor_id(), RemoteSignal::Kill(reason))),
+            Signal::Monitor(r, from) => Some((from.actor_id(), RemoteSignal::Monitor(r))),
+            Signal::Demonitor(r, from) => Some((from.actor_id(), RemoteSignal::Demonitor(r))),
+            Signal::Down(down) => Some((
+                down.pid.actor_id(),
+                RemoteSignal::Down(down.monitor_ref, down.reason),
+            )),
+            Signal::TrapExit(_) | Signal::Terminate(_) => None,
+            Signal::NoConnection(_) => None,
+        }
+    }
+}
+
+impl RemoteSignal {
+    /// Rebuilds the [`Signal`] sent by `from`
+    pub fn into_signal(self, from: Pid) -> Signal {
+        match self {
+            RemoteSignal::Link => Signal::Link(from),
+            RemoteSignal::Unlink => Signal::Unlink(from),
+            RemoteSignal::Exit(reason) => Signal::Exit(from, reason),
+            RemoteSignal::Kill(reason) => Signal::Kill(from, reason),
+            RemoteSignal::Monitor(r) => Signal::Monitor(r, from),
+            RemoteSignal::Demonitor(r) => Signal::Demonitor(r, from),
+            RemoteSignal::Down(monitor_ref, reason) => Signal::Down(Down {
+                monitor_ref,
+                pid: from,
+                reason,
+            }),
+        }
+    }
+}
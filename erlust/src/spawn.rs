@@ -2,16 +2,316 @@ use futures::{
     task::{Spawn, SpawnError, SpawnExt},
     Future,
 };
+use std::{
+    sync::{Arc, RwLock},
+    thread,
+};
+
+use crate::{
+    local_channel::{with_my_channel, QUEUE_BUFFER},
+    signal::Signal,
+    Error, LocalChannelUpdater, Pid, Ref,
+};
+
+lazy_static! {
+    /// The executor used by [`spawn`] and [`spawn_link`]
+    static ref EXECUTOR: RwLock<Option<Arc<dyn Spawn + Send + Sync>>> = RwLock::new(None);
+}
+
+/// Sets the executor used by [`spawn`] and [`spawn_link`] for all the actors
+/// of the local theater
+///
+/// This is meant to be called once at startup. Calling it again replaces the
+/// executor for the actors spawned afterwards.
+pub fn set_executor<Spwn>(spawner: Spwn)
+where
+    Spwn: 'static + Spawn + Send + Sync,
+{
+    *EXECUTOR.write().unwrap() = Some(Arc::new(spawner));
+}
+
+/// Runs `f` with the executor set by [`set_executor`]
+///
+/// Fails with a shutdown error if no executor was set.
+fn with_executor<T>(
+    f: impl FnOnce(&mut &(dyn Spawn + Send + Sync)) -> Result<T, SpawnError>,
+) -> Result<T, SpawnError> {
+    // Do not keep the lock while spawning, in case the executor runs the
+    // actor right away
+    let executor = EXECUTOR.read().unwrap().clone();
+    match executor {
+        Some(executor) => f(&mut &*executor),
+        None => Err(SpawnError::shutdown()),
+    }
+}
 
-use crate::LocalChannelUpdater;
+/// Runs `fut` in the background, outside of any actor
+///
+/// This uses the tokio runtime the caller runs in if any, then the executor
+/// set by [`set_executor`], and falls back to a new thread.
+pub(crate) fn spawn_detached<Fut>(fut: Fut)
+where
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    #[cfg(feature = "tokio")]
+    {
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(fut);
+            return;
+        }
+    }
+    let mut fut = Some(fut);
+    let spawned = with_executor(|executor| executor.spawn(fut.take().unwrap()));
+    if let (Err(_), Some(fut)) = (spawned, fut) {
+        thread::spawn(move || futures::executor::block_on(fut));
+    }
+}
 
-// TODO(A): figure out a way to not require this `spawner` argument
+/// Spawns `fut` as a new actor, on the executor set by [`set_executor`], and
+/// returns its [`Pid`]
+///
+/// Fails if no executor was set.
 // TODO(B): consider making the output impl Future again as future-proofing
-pub fn spawn<Spwn, Fut>(spawner: &mut Spwn, fut: Fut) -> Result<(), SpawnError>
+pub fn spawn<Fut>(fut: Fut) -> Result<Pid, Error>
+where
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let task = LocalChannelUpdater::new(fut);
+    with_executor(|executor| spawn_task(executor, task, false, None)).map_err(Error::Spawn)
+}
+
+/// Spawns `fut` as a new actor, on `spawner`, and returns its [`Pid`]
+pub fn spawn_on<Spwn, Fut>(spawner: &mut Spwn, fut: Fut) -> Result<Pid, Error>
 where
     Spwn: Spawn,
     Fut: Future<Output = ()> + Send + 'static,
+{
+    spawn_task(spawner, LocalChannelUpdater::new(fut), false, None).map_err(Error::Spawn)
+}
+
+/// Spawns `fut` as a new actor, atomically linked to the currently running
+/// actor, on the executor set by [`set_executor`], and returns its [`Pid`]
+///
+/// See [`Pid::link`] for the semantics of links. Fails if no executor was
+/// set.
+///
+/// Panics if not called from an actor task.
+pub fn spawn_link<Fut>(fut: Fut) -> Result<Pid, Error>
+where
+    Fut: Future<Output = ()> + Send + 'static,
 {
     let task = LocalChannelUpdater::new(fut);
-    spawner.spawn(task)
+    with_executor(|executor| spawn_task(executor, task, true, None)).map_err(Error::Spawn)
+}
+
+/// Spawns `fut` as a new actor, atomically linked to the currently running
+/// actor, on `spawner`, and returns its [`Pid`]
+///
+/// See [`Pid::link`] for the semantics of links.
+///
+/// Panics if not called from an actor task.
+pub fn spawn_link_on<Spwn, Fut>(spawner: &mut Spwn, fut: Fut) -> Result<Pid, Error>
+where
+    Spwn: Spawn,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    spawn_task(spawner, LocalChannelUpdater::new(fut), true, None).map_err(Error::Spawn)
+}
+
+/// Spawns `task` on `spawner`, atomically linking it to and making it
+/// monitored by the currently running actor if requested
+fn spawn_task<Spwn, Fut>(
+    spawner: &mut Spwn,
+    task: LocalChannelUpdater<Fut>,
+    link: bool,
+    monitor: Option<Ref>,
+) -> Result<Pid, SpawnError>
+where
+    Spwn: Spawn,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let child = task.pid();
+    if !link && monitor.is_none() {
+        return spawner.spawn(task).map(|()| child);
+    }
+
+    // The child has not been polled yet, so it will handle these signals
+    // before anything else
+    let me = Pid::me();
+    if link {
+        child.signal_local(Signal::Link(me.clone()));
+        me.signal_local(Signal::Link(child.clone()));
+    }
+    if let Some(monitor_ref) = monitor {
+        child.signal_local(Signal::Monitor(monitor_ref, me.clone()));
+        with_my_channel(|chan| chan.watching.push((monitor_ref, child.clone())));
+    }
+    match spawner.spawn(task) {
+        Ok(()) => Ok(child),
+        Err(e) => {
+            if link {
+                me.signal_local(Signal::Unlink(child));
+            }
+            if let Some(monitor_ref) = monitor {
+                with_my_channel(|chan| chan.watching.retain(|(r, _)| *r != monitor_ref));
+            }
+            Err(e)
+        }
+    }
+}
+
+/// What to do when a message has to be added to a full waiting queue
+///
+/// See [`SpawnOptions::waiting_limit`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Overflow {
+    /// Drop the oldest message of the waiting queue
+    DropOldest,
+
+    /// Drop the message that was to be added
+    DropNewest,
+
+    /// Terminate the actor with reason [`ExitReason::Overflow`]
+    ///
+    /// [`ExitReason::Overflow`]: crate::ExitReason::Overflow
+    Kill,
+}
+
+/// Options for spawning an actor
+#[derive(Clone, Debug)]
+pub struct SpawnOptions {
+    capacity: usize,
+    waiting_limit: Option<(usize, Overflow)>,
+    name: Option<String>,
+    link: bool,
+}
+
+impl SpawnOptions {
+    /// Builds the options used by [`spawn`]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> SpawnOptions {
+        SpawnOptions {
+            capacity: QUEUE_BUFFER,
+            waiting_limit: None,
+            name: None,
+            link: false,
+        }
+    }
+
+    /// Sets the number of messages that can be waiting in the mailbox before
+    /// senders have to wait, 64 by default
+    ///
+    /// Each sender can still send one message more than this.
+    pub fn mailbox_capacity(mut self, capacity: usize) -> SpawnOptions {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Limits to `limit` the number of messages skipped by
+    /// [`receive`](crate::receive) that are kept for later, and sets what
+    /// happens when more messages are skipped
+    ///
+    /// By default, the waiting queue is unbounded.
+    pub fn waiting_limit(mut self, limit: usize, overflow: Overflow) -> SpawnOptions {
+        self.waiting_limit = Some((limit, overflow));
+        self
+    }
+
+    /// Registers the actor under `name` (see [`register`](crate::register))
+    pub fn name(mut self, name: &str) -> SpawnOptions {
+        self.name = Some(String::from(name));
+        self
+    }
+
+    /// Atomically links the actor to the currently running actor
+    ///
+    /// See [`spawn_link`].
+    pub fn link(mut self) -> SpawnOptions {
+        self.link = true;
+        self
+    }
+
+    /// Spawns `fut` as a new actor with these options, on the executor set
+    /// by [`set_executor`], and returns its [`Pid`]
+    ///
+    /// Fails if no executor was set, or if the name could not be registered.
+    ///
+    /// Panics if linking and not called from an actor task.
+    pub fn spawn<Fut>(&self, fut: Fut) -> Result<Pid, Error>
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        // Build the task here, so that errors when registering the name are
+        // not hidden by a missing executor
+        let task = self.task(fut)?;
+        with_executor(|executor| spawn_task(executor, task, self.link, None)).map_err(Error::Spawn)
+    }
+
+    /// Spawns `fut` as a new actor with these options, on `spawner`, and
+    /// returns its [`Pid`]
+    ///
+    /// Fails if the name could not be registered.
+    ///
+    /// Panics if linking and not called from an actor task.
+    pub fn spawn_on<Spwn, Fut>(&self, spawner: &mut Spwn, fut: Fut) -> Result<Pid, Error>
+    where
+        Spwn: Spawn,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let task = self.task(fut)?;
+        spawn_task(spawner, task, self.link, None).map_err(Error::Spawn)
+    }
+
+    /// Same as [`spawn`](SpawnOptions::spawn), but also atomically makes the
+    /// currently running actor monitor the new actor, and returns the
+    /// [`Ref`] identifying the monitor along with its [`Pid`]
+    ///
+    /// See [`Pid::monitor`].
+    ///
+    /// Panics if not called from an actor task.
+    pub fn spawn_monitor<Fut>(&self, fut: Fut) -> Result<(Pid, Ref), Error>
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let task = self.task(fut)?;
+        let monitor_ref = Ref::new();
+        with_executor(|executor| spawn_task(executor, task, self.link, Some(monitor_ref)))
+            .map(|pid| (pid, monitor_ref))
+            .map_err(Error::Spawn)
+    }
+
+    /// Same as [`spawn_on`](SpawnOptions::spawn_on), but also atomically
+    /// makes the currently running actor monitor the new actor, and returns
+    /// the [`Ref`] identifying the monitor along with its [`Pid`]
+    ///
+    /// See [`Pid::monitor`].
+    ///
+    /// Panics if not called from an actor task.
+    pub fn spawn_monitor_on<Spwn, Fut>(
+        &self,
+        spawner: &mut Spwn,
+        fut: Fut,
+    ) -> Result<(Pid, Ref), Error>
+    where
+        Spwn: Spawn,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let task = self.task(fut)?;
+        let monitor_ref = Ref::new();
+        spawn_task(spawner, task, self.link, Some(monitor_ref))
+            .map(|pid| (pid, monitor_ref))
+            .map_err(Error::Spawn)
+    }
+
+    fn task<Fut>(&self, fut: Fut) -> Result<LocalChannelUpdater<Fut>, Error>
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let task = LocalChannelUpdater::with_options(fut, self.capacity, self.waiting_limit);
+        if let Some(name) = &self.name {
+            // The name is unregistered when `task` is dropped if spawning fails
+            crate::register(name, &task.pid())?;
+        }
+        Ok(task)
+    }
 }
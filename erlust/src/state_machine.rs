@@ -0,0 +1,224 @@
+//! Actors whose messages are handled differently depending on their state,
+//! see [`StateMachine`]
+
+use futures::Future;
+use std::{
+    any::TypeId,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    receive::{receive, receive_timeout},
+    ExitReason, Message, ReceiveResult, ReceivedMessage,
+};
+
+/// The message types a [`StateMachine`] handles in a given state
+#[derive(Clone, Debug, Default)]
+pub struct Handles {
+    types: Vec<(TypeId, &'static str)>,
+}
+
+impl Handles {
+    /// Handles no message at all
+    pub fn new() -> Handles {
+        Handles { types: Vec::new() }
+    }
+
+    /// Also handles messages of type `M`
+    pub fn message<M: Message>(mut self) -> Handles {
+        self.types.push((TypeId::of::<M>(), M::tag()));
+        self
+    }
+
+    fn matches(&self, msg: &ReceivedMessage) -> bool {
+        match msg {
+            ReceivedMessage::Local((_, m)) => {
+                let type_id = m.as_any().type_id();
+                self.types.iter().any(|(t, _)| *t == type_id)
+            }
+            ReceivedMessage::Remote((_, m)) => self.types.iter().any(|(_, tag)| *tag == m.tag),
+        }
+    }
+}
+
+/// What happened to a [`StateMachine`]
+pub enum StateEvent {
+    /// A message handled in the current state was received
+    Message(ReceivedMessage),
+
+    /// The state timeout set by [`Transition::state_timeout`] elapsed
+    StateTimeout,
+
+    /// The event timeout set by [`Transition::event_timeout`] elapsed
+    EventTimeout,
+}
+
+enum Target<State> {
+    Keep,
+    To(State),
+    Stop(ExitReason),
+}
+
+/// What a [`StateMachine`] should do after handling an event
+pub struct Transition<State> {
+    target: Target<State>,
+    state_timeout: Option<Duration>,
+    event_timeout: Option<Duration>,
+}
+
+impl<State> Transition<State> {
+    fn new(target: Target<State>) -> Transition<State> {
+        Transition {
+            target,
+            state_timeout: None,
+            event_timeout: None,
+        }
+    }
+
+    /// Stays in the current state
+    ///
+    /// Messages postponed in this state stay postponed, and the state
+    /// timeout keeps running.
+    pub fn keep() -> Transition<State> {
+        Transition::new(Target::Keep)
+    }
+
+    /// Moves to `state`
+    ///
+    /// If `state` is not the current state, the state timeout is cancelled,
+    /// and postponed messages are considered again, in the order they were
+    /// received.
+    pub fn to(state: State) -> Transition<State> {
+        Transition::new(Target::To(state))
+    }
+
+    /// Calls [`StateMachine::terminate`], then terminates with `reason`
+    pub fn stop(reason: ExitReason) -> Transition<State> {
+        Transition::new(Target::Stop(reason))
+    }
+
+    /// Sends a [`StateEvent::StateTimeout`] if the machine is still in the
+    /// resulting state after `timeout`
+    ///
+    /// This replaces any running state timeout.
+    pub fn state_timeout(mut self, timeout: Duration) -> Transition<State> {
+        self.state_timeout = Some(timeout);
+        self
+    }
+
+    /// Sends a [`StateEvent::EventTimeout`] if no other event happens in the
+    /// next `timeout`
+    ///
+    /// Postponed messages are not events, and do not cancel this timeout.
+    pub fn event_timeout(mut self, timeout: Duration) -> Transition<State> {
+        self.event_timeout = Some(timeout);
+        self
+    }
+}
+
+/// An actor that handles its messages depending on its current state, like
+/// Erlang's `gen_statem`
+///
+/// Each state declares the message types it handles, with
+/// [`StateMachine::handles`]. The other messages are postponed: they stay in
+/// the mailbox until the state changes, and are then considered again.
+///
+/// The machine is run by [`run_state_machine`]. [`StateMachine::terminate`]
+/// is called when a handler asks the machine to stop. It is not called if the
+/// machine is killed by an exit signal, or if a handler panics.
+pub trait StateMachine: 'static + Send + Sized {
+    /// The states of the machine
+    type State: 'static + Send + Sync + PartialEq;
+
+    /// Called once, from the machine actor, and returns the initial state
+    ///
+    /// If it fails, the machine terminates with the returned reason, without
+    /// calling [`StateMachine::terminate`].
+    fn init(&mut self) -> impl Send + Future<Output = Result<Self::State, ExitReason>>;
+
+    /// Returns the message types handled in `state`
+    fn handles(&self, state: &Self::State) -> Handles;
+
+    /// Handles `event`, that happened in `state`
+    fn handle_event(
+        &mut self,
+        state: &Self::State,
+        event: StateEvent,
+    ) -> impl Send + Future<Output = Transition<Self::State>>;
+
+    /// Called when a handler returned [`Transition::stop`], right before the
+    /// machine terminates with `reason`
+    fn terminate(
+        &mut self,
+        state: &Self::State,
+        reason: &ExitReason,
+    ) -> impl Send + Future<Output = ()> {
+        let _ = (state, reason);
+        async {}
+    }
+}
+
+/// Waits for the next message `handles` accepts, or for `deadline`
+async fn next_message(handles: Handles, deadline: Option<Instant>) -> Option<ReceivedMessage> {
+    let handle = async move |msg: &mut Option<ReceivedMessage>| match handles
+        .matches(msg.as_ref().unwrap())
+    {
+        true => ReceiveResult::Use(msg.take().unwrap()),
+        false => ReceiveResult::Skip,
+    };
+    match deadline {
+        Some(d) => receive_timeout(handle, d.saturating_duration_since(Instant::now())).await,
+        None => Some(receive(handle).await),
+    }
+}
+
+/// Runs `machine` until it stops
+///
+/// The returned future is meant to be spawned as an actor, eg. as the start
+/// function of a [`ChildSpec`](crate::ChildSpec).
+pub async fn run_state_machine<M: StateMachine>(mut machine: M) {
+    let mut state = match machine.init().await {
+        Ok(state) => state,
+        Err(reason) => return crate::exit(reason).await,
+    };
+    let mut state_deadline = None;
+    let mut event_deadline = None;
+    loop {
+        let deadline = match (state_deadline, event_deadline) {
+            (Some(s), Some(e)) => Some(Instant::min(s, e)),
+            (s, e) => s.or(e),
+        };
+        let event = match next_message(machine.handles(&state), deadline).await {
+            Some(msg) => StateEvent::Message(msg),
+            None if state_deadline == deadline => {
+                state_deadline = None;
+                StateEvent::StateTimeout
+            }
+            None => StateEvent::EventTimeout,
+        };
+        // Any event cancels the event timeout
+        event_deadline = None;
+
+        let transition = machine.handle_event(&state, event).await;
+        match transition.target {
+            Target::Keep => (),
+            Target::To(next) => {
+                if next != state {
+                    state_deadline = None;
+                }
+                state = next;
+            }
+            Target::Stop(reason) => {
+                machine.terminate(&state, &reason).await;
+                return crate::exit(reason).await;
+            }
+        }
+        let now = Instant::now();
+        if let Some(timeout) = transition.state_timeout {
+            state_deadline = Some(now + timeout);
+        }
+        if let Some(timeout) = transition.event_timeout {
+            event_deadline = Some(now + timeout);
+        }
+    }
+}
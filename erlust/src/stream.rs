@@ -0,0 +1,307 @@
+//! Connection management shared by the stream-based theaters
+//!
+//! Each connection is opened by the theater that sends first, and only
+//! carries records in this direction: the theater that accepts it only reads
+//! from it, and opens its own connection for answering. So a connection
+//! accepted from a remote theater never receives what is sent to this
+//! theater, whatever it claims to be.
+//!
+//! With integers in big-endian, a connection starts with:
+//!  * its kind, as a `u8`: `0` for sending records, `1` for verifying a
+//!    theater
+//!  * what the connecting theater announces for reaching it, as a `u32`
+//!    length followed by this many bytes
+//!
+//! A connection for verifying a theater is then followed by a `u128`
+//! challenge, and the accepting theater answers with a `u8`: `1` if it
+//! received this challenge from the connecting theater, `0` otherwise.
+//!
+//! When the transport does not authenticate theaters (see
+//! [`StreamTheater::CONNECT_BACK`]), the accepting theater of a connection
+//! for sending records then checks the connecting theater is the one it
+//! announced: it sends a random `u128` challenge, waits for a `u8` from the
+//! connecting theater, and connects back to the announced theater to verify
+//! it received this challenge. In all cases, the accepting theater then
+//! answers with a `u8`, `1`, if the connection is accepted, and records
+//! follow, framed as described in [`frame`](crate::frame).
+
+use futures::{future::Future, lock::Mutex};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex as SyncMutex,
+    },
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, WriteHalf};
+
+use crate::{connection_lost, frame, ActorId, Error, Theater};
+
+/// Maximum size of what a connecting theater announces
+const MAX_ANNOUNCE_SIZE: usize = 4096;
+
+/// Kind of the connections for sending records
+const SEND: u8 = 0;
+
+/// Kind of the connections for verifying a theater
+const VERIFY: u8 = 1;
+
+/// Answer of a theater accepting a connection or a challenge
+const ACCEPTED: u8 = 1;
+
+/// A [`Theater`] exchanging records over streams, identified by the theater
+/// itself
+pub trait StreamTheater: Theater + Eq + Hash {
+    type Stream: 'static + AsyncRead + AsyncWrite + Send + Unpin;
+
+    /// Whether remote theaters are verified by connecting back to them,
+    /// because the transport does not authenticate them
+    const CONNECT_BACK: bool;
+
+    /// The connections opened by the local theater, by remote theater
+    fn connections() -> &'static Connections<Self>;
+
+    /// Returns what the local theater announces to the theaters it connects
+    /// to, for them to reach it
+    ///
+    /// Fails if the local theater is not listening.
+    fn announce() -> io::Result<Vec<u8>>;
+
+    /// Opens a stream to `self`, authenticating the remote theater if the
+    /// transport allows it
+    ///
+    /// Returns the stream along with the remote theater, as the theaters
+    /// accepting connections from it identify it.
+    fn open(&self) -> impl Send + Future<Output = io::Result<(Self::Stream, Self)>>;
+}
+
+/// The connection used for sending to a remote theater, if any, along with
+/// an identifier for telling it apart from later connections
+type Slot<S> = Arc<Mutex<Option<(u64, WriteHalf<S>)>>>;
+
+/// The connections opened by the local theater to the remote theaters of
+/// type `T`
+pub struct Connections<T: StreamTheater> {
+    slots:   SyncMutex<HashMap<T, Slot<T::Stream>>>,
+    next_id: AtomicU64,
+
+    /// The number of records received from remote theaters that could not
+    /// be delivered
+    undelivered: AtomicU64,
+
+    /// The challenges received from the theaters the local theater is
+    /// connecting to, and not yet answered
+    challenges: SyncMutex<HashSet<(u128, T)>>,
+}
+
+impl<T: StreamTheater> Connections<T> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Connections<T> {
+        Connections {
+            slots: SyncMutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            undelivered: AtomicU64::new(0),
+            challenges: SyncMutex::new(HashSet::new()),
+        }
+    }
+
+    /// Returns the number of records received from remote theaters of type
+    /// `T` that could not be delivered to local actors
+    pub fn undelivered(&self) -> u64 {
+        self.undelivered.load(Ordering::Relaxed)
+    }
+
+    fn slot(&self, theater: &T) -> Slot<T::Stream> {
+        self.slots
+            .lock()
+            .unwrap()
+            .entry(theater.clone())
+            .or_default()
+            .clone()
+    }
+}
+
+/// Sends a record to `theater`, opening a connection if there is none
+///
+/// If the connection cannot be opened or fails, local actors are notified
+/// it was lost.
+pub async fn send<T: StreamTheater>(
+    theater: &T,
+    from: ActorId,
+    to: ActorId,
+    tag: &'static str,
+    msg: Vec<u8>,
+) -> Result<(), Error> {
+    let record = frame::encode(from, to, tag, &msg).map_err(Error::transport)?;
+    let slot = T::connections().slot(theater);
+    let mut slot = slot.lock().await;
+    if slot.is_none() {
+        *slot = Some(connect(theater).await.map_err(|e| {
+            connection_lost(theater);
+            Error::transport(e)
+        })?);
+    }
+    let write = &mut slot.as_mut().unwrap().1;
+    // Some streams buffer what is written, so it must be flushed to be sent
+    let res = match write.write_all(&record).await {
+        Ok(()) => write.flush().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        *slot = None;
+        connection_lost(theater);
+        return Err(Error::transport(e));
+    }
+    Ok(())
+}
+
+/// A challenge received from `theater`, that the local theater answers to
+/// until it is dropped
+struct Challenge<T: StreamTheater> {
+    challenge: (u128, T),
+}
+
+impl<T: StreamTheater> Challenge<T> {
+    fn new(challenge: u128, theater: &T) -> Challenge<T> {
+        let challenge = (challenge, theater.clone());
+        let challenges = &T::connections().challenges;
+        challenges.lock().unwrap().insert(challenge.clone());
+        Challenge { challenge }
+    }
+}
+
+impl<T: StreamTheater> Drop for Challenge<T> {
+    fn drop(&mut self) {
+        let challenges = &T::connections().challenges;
+        challenges.lock().unwrap().remove(&self.challenge);
+    }
+}
+
+/// Opens a stream to `theater`, starting a connection of kind `kind`, and
+/// returns it along with the remote theater as identified by the transport
+async fn open<T: StreamTheater>(theater: &T, kind: u8) -> io::Result<(T::Stream, T)> {
+    let here = T::announce()?;
+    let (mut stream, peer) = theater.open().await?;
+    stream.write_u8(kind).await?;
+    stream.write_u32(here.len() as u32).await?;
+    stream.write_all(&here).await?;
+    Ok((stream, peer))
+}
+
+fn rejected() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "the remote theater rejected the connection",
+    )
+}
+
+/// Opens a connection to `theater`, and starts watching for it to close
+async fn connect<T: StreamTheater>(theater: &T) -> io::Result<(u64, WriteHalf<T::Stream>)> {
+    let (mut stream, peer) = open(theater, SEND).await?;
+    stream.flush().await?;
+    // Answer the challenge of `theater` while it connects back to the local
+    // theater, as which it identifies itself then
+    let _challenge = match T::CONNECT_BACK {
+        true => {
+            let challenge = Challenge::new(stream.read_u128().await?, &peer);
+            stream.write_u8(ACCEPTED).await?;
+            stream.flush().await?;
+            Some(challenge)
+        }
+        false => None,
+    };
+    if stream.read_u8().await? != ACCEPTED {
+        return Err(rejected());
+    }
+    let (read, write) = tokio::io::split(stream);
+    let id = T::connections().next_id.fetch_add(1, Ordering::Relaxed);
+    tokio::spawn(watch(theater.clone(), id, read));
+    Ok((id, write))
+}
+
+/// Waits for connection `id` to `theater` to close, and then notifies local
+/// actors it was lost
+///
+/// Nothing is expected on `read`, as remote theaters answer through their
+/// own connections.
+async fn watch<T: StreamTheater, R: AsyncRead + Unpin>(theater: T, id: u64, mut read: R) {
+    let _ = tokio::io::copy(&mut read, &mut tokio::io::sink()).await;
+    let slot = T::connections().slot(&theater);
+    let mut slot = slot.lock().await;
+    if slot.as_ref().is_some_and(|(i, _)| *i == id) {
+        *slot = None;
+        connection_lost(&theater);
+    }
+}
+
+/// Verifies that the remote theater at the other end of `stream` is
+/// `theater`, by connecting back to it
+async fn verify<T: StreamTheater>(theater: &T, stream: &mut T::Stream) -> io::Result<bool> {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("failed to get random bytes");
+    let challenge = u128::from_ne_bytes(bytes);
+    stream.write_u128(challenge).await?;
+    stream.flush().await?;
+    // Wait for the remote theater to be ready to answer the challenge
+    stream.read_u8().await?;
+    let (mut back, _) = open(theater, VERIFY).await?;
+    back.write_u128(challenge).await?;
+    back.flush().await?;
+    Ok(back.read_u8().await? == ACCEPTED)
+}
+
+/// Handles a connection opened by a remote theater, identified by
+/// `identify` from what it announced
+///
+/// The records sent on connections for sending records are injected, once
+/// the remote theater is verified if needed. The stream must already be
+/// authenticated, if the transport allows it. The connection is dropped if
+/// `identify` returns `None`.
+pub async fn accept<T, F>(mut stream: T::Stream, identify: F)
+where
+    T: StreamTheater,
+    F: FnOnce(&[u8]) -> Option<T>,
+{
+    let kind = match stream.read_u8().await {
+        Ok(kind) => kind,
+        Err(_) => return,
+    };
+    let len = match stream.read_u32().await {
+        Ok(len) if len as usize <= MAX_ANNOUNCE_SIZE => len as usize,
+        _ => return,
+    };
+    let mut announced = vec![0; len];
+    if stream.read_exact(&mut announced).await.is_err() {
+        return;
+    }
+    let theater = match identify(&announced) {
+        Some(theater) => theater,
+        None => return,
+    };
+    match kind {
+        SEND => (),
+        VERIFY => {
+            let challenge = match stream.read_u128().await {
+                Ok(challenge) => challenge,
+                Err(_) => return,
+            };
+            let challenges = &T::connections().challenges;
+            let known = challenges.lock().unwrap().contains(&(challenge, theater));
+            // Ignore errors, as the remote theater then gave up verifying
+            let _ = stream.write_u8(known as u8).await;
+            let _ = stream.flush().await;
+            return;
+        }
+        _ => return,
+    }
+    if T::CONNECT_BACK && !verify(&theater, &mut stream).await.unwrap_or(false) {
+        return;
+    }
+    if stream.write_u8(ACCEPTED).await.is_err() || stream.flush().await.is_err() {
+        return;
+    }
+    let undelivered = &T::connections().undelivered;
+    frame::inject_all(&mut stream, Box::new(theater), undelivered).await;
+}
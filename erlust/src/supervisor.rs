@@ -0,0 +1,303 @@
+//! Supervisors, that restart their children when they terminate
+
+use futures::{
+    future::{self, BoxFuture, Either},
+    task::Spawn,
+    Future, FutureExt,
+};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    receive::{downcast, receive},
+    spawn_link_on,
+    timer::Delay,
+    trap_exit, Error, Exit, ExitReason, Pid, ReceiveResult, ReceivedMessage,
+};
+
+/// When a child should be restarted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Restart {
+    /// The child is always restarted
+    Permanent,
+
+    /// The child is restarted only if it terminated abnormally, ie. with
+    /// another reason than [`ExitReason::Normal`] or [`ExitReason::Shutdown`]
+    Transient,
+
+    /// The child is never restarted
+    Temporary,
+}
+
+/// Which children are restarted when a child has to be restarted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Strategy {
+    /// Only the child that terminated is restarted
+    OneForOne,
+
+    /// All the children are terminated, then restarted
+    OneForAll,
+
+    /// The children started after the child that terminated are terminated,
+    /// then they are restarted along with the child that terminated
+    RestForOne,
+}
+
+/// The specification of a child of a [`Supervisor`]
+pub struct ChildSpec {
+    start:    Box<dyn Send + Sync + FnMut() -> BoxFuture<'static, ()>>,
+    restart:  Restart,
+    shutdown: Duration,
+}
+
+impl ChildSpec {
+    /// Builds a [`Restart::Permanent`] child, that will be started by calling
+    /// `start` and spawning the returned future as an actor, and that has 5
+    /// seconds to terminate when shut down
+    pub fn new<F, Fut>(mut start: F) -> ChildSpec
+    where
+        F: 'static + Send + Sync + FnMut() -> Fut,
+        Fut: 'static + Send + Future<Output = ()>,
+    {
+        ChildSpec {
+            start:    Box::new(move || start().boxed()),
+            restart:  Restart::Permanent,
+            shutdown: Duration::from_secs(5),
+        }
+    }
+
+    /// Sets when the child should be restarted
+    pub fn restart(mut self, restart: Restart) -> ChildSpec {
+        self.restart = restart;
+        self
+    }
+
+    /// Sets how long the child has to terminate after being asked to shut
+    /// down, before being killed
+    ///
+    /// A timeout of zero means the child is killed right away.
+    pub fn shutdown(mut self, timeout: Duration) -> ChildSpec {
+        self.shutdown = timeout;
+        self
+    }
+
+    fn should_restart(&self, reason: &ExitReason) -> bool {
+        match self.restart {
+            Restart::Permanent => true,
+            Restart::Transient => *reason != ExitReason::Normal && *reason != ExitReason::Shutdown,
+            Restart::Temporary => false,
+        }
+    }
+}
+
+/// A child of a running [`Supervisor`]
+struct Child {
+    spec: ChildSpec,
+    pid:  Option<Pid>,
+}
+
+/// An actor that starts children, and restarts them when they terminate
+///
+/// Children are linked to the supervisor, and the supervisor traps exits.
+/// If children need to be restarted more than a given number of times in a
+/// given period of time (see [`Supervisor::intensity`]), the supervisor shuts
+/// down all its children and terminates with reason
+/// [`ExitReason::Shutdown`]. If it receives an exit signal with a reason
+/// other than [`ExitReason::Normal`] from an actor that is not one of its
+/// children, eg. its own supervisor, it shuts down all its children too, and
+/// terminates with the same reason.
+///
+/// Children are started in the order in which they were added, and shut down
+/// in the reverse order. If a child cannot be spawned, the supervisor shuts
+/// down the other children and terminates with reason
+/// [`ExitReason::StartFailed`].
+///
+/// The supervisor only handles [`Exit`] messages: other messages are left in
+/// its mailbox.
+pub struct Supervisor<Spwn> {
+    spawner: Spwn,
+    strategy: Strategy,
+    max_restarts: usize,
+    period: Duration,
+    children: Vec<Child>,
+    restarts: VecDeque<Instant>,
+    deferred: VecDeque<Exit>,
+}
+
+/// Waits for the next [`Exit`] message, leaving other messages in the
+/// mailbox
+async fn next_exit() -> Exit {
+    receive(
+        async |msg: &mut Option<ReceivedMessage>| match downcast::<Exit>(msg.take().unwrap()) {
+            Ok((_, exit)) => ReceiveResult::Use(*exit),
+            Err(m) => {
+                *msg = Some(m);
+                ReceiveResult::Skip
+            }
+        },
+    )
+    .await
+}
+
+impl<Spwn: Spawn> Supervisor<Spwn> {
+    /// Builds a supervisor with no children, that will spawn its children
+    /// with `spawner` and restart them according to `strategy`
+    ///
+    /// By default, the supervisor allows for at most one restart every 5
+    /// seconds.
+    pub fn new(spawner: Spwn, strategy: Strategy) -> Supervisor<Spwn> {
+        Supervisor {
+            spawner,
+            strategy,
+            max_restarts: 1,
+            period: Duration::from_secs(5),
+            children: Vec::new(),
+            restarts: VecDeque::new(),
+            deferred: VecDeque::new(),
+        }
+    }
+
+    /// Sets the maximum number of restarts allowed in `period`
+    pub fn intensity(mut self, max_restarts: usize, period: Duration) -> Supervisor<Spwn> {
+        self.max_restarts = max_restarts;
+        self.period = period;
+        self
+    }
+
+    /// Adds a child to the supervisor
+    pub fn child(mut self, spec: ChildSpec) -> Supervisor<Spwn> {
+        self.children.push(Child { spec, pid: None });
+        self
+    }
+
+    /// Runs the supervisor
+    ///
+    /// The returned future is meant to be spawned as an actor.
+    pub async fn run(mut self) {
+        trap_exit(true);
+        for i in 0..self.children.len() {
+            if let Err(e) = self.start(i) {
+                return self.fail(e).await;
+            }
+        }
+
+        loop {
+            let exit = match self.deferred.pop_front() {
+                Some(exit) => exit,
+                None => next_exit().await,
+            };
+            let pid = Some(&exit.pid);
+            let idx = match self.children.iter().position(|c| c.pid.as_ref() == pid) {
+                Some(idx) => idx,
+                None if exit.reason == ExitReason::Normal => continue,
+                None => {
+                    self.shutdown_all().await;
+                    return crate::exit(exit.reason).await;
+                }
+            };
+            self.children[idx].pid = None;
+            if !self.children[idx].spec.should_restart(&exit.reason) {
+                continue;
+            }
+            if !self.note_restart() {
+                self.shutdown_all().await;
+                return crate::exit(ExitReason::Shutdown).await;
+            }
+
+            let restarted = match self.strategy {
+                Strategy::OneForOne => idx..idx + 1,
+                Strategy::OneForAll => 0..self.children.len(),
+                Strategy::RestForOne => idx..self.children.len(),
+            };
+            for i in restarted.clone().rev() {
+                self.terminate(i).await;
+            }
+            for i in restarted {
+                if i == idx || self.children[i].spec.restart != Restart::Temporary {
+                    if let Err(e) = self.start(i) {
+                        return self.fail(e).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Starts the `i`-th child
+    fn start(&mut self, i: usize) -> Result<(), Error> {
+        let fut = (self.children[i].spec.start)();
+        let pid = spawn_link_on(&mut self.spawner, fut)?;
+        self.children[i].pid = Some(pid);
+        Ok(())
+    }
+
+    /// Shuts down all the children, and terminates because a child could not
+    /// be spawned
+    async fn fail(&mut self, error: Error) {
+        self.shutdown_all().await;
+        crate::exit(ExitReason::StartFailed(error.to_string())).await
+    }
+
+    /// Records a restart, returning `false` iff the restart intensity has
+    /// been exceeded
+    fn note_restart(&mut self) -> bool {
+        let now = Instant::now();
+        self.restarts.push_back(now);
+        while let Some(t) = self.restarts.front() {
+            if now.duration_since(*t) <= self.period {
+                break;
+            }
+            self.restarts.pop_front();
+        }
+        self.restarts.len() <= self.max_restarts
+    }
+
+    /// Shuts down the `i`-th child, if it is running, and waits for it to
+    /// terminate
+    async fn terminate(&mut self, i: usize) {
+        let pid = match self.children[i].pid.take() {
+            Some(pid) => pid,
+            None => return,
+        };
+        if let Some(idx) = self.deferred.iter().position(|e| e.pid == pid) {
+            // Already terminated on its own
+            self.deferred.remove(idx);
+            return;
+        }
+
+        let shutdown = self.children[i].spec.shutdown;
+        let mut kill = if shutdown == Duration::from_secs(0) {
+            pid.exit(ExitReason::Kill).await;
+            None
+        } else {
+            pid.exit(ExitReason::Shutdown).await;
+            Some(Delay::new(shutdown))
+        };
+
+        loop {
+            let exit = match kill {
+                Some(ref mut delay) => match future::select(next_exit().boxed(), delay).await {
+                    Either::Left((exit, _)) => exit,
+                    Either::Right(((), _)) => {
+                        pid.exit(ExitReason::Kill).await;
+                        kill = None;
+                        continue;
+                    }
+                },
+                None => next_exit().await,
+            };
+            if exit.pid == pid {
+                return;
+            }
+            self.deferred.push_back(exit);
+        }
+    }
+
+    /// Shuts down all the children, in the reverse order of their starting
+    async fn shutdown_all(&mut self) {
+        for i in (0..self.children.len()).rev() {
+            self.terminate(i).await;
+        }
+    }
+}
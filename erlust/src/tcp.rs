@@ -0,0 +1,170 @@
+//! A [`Theater`] communicating over TCP, with the `tcp` feature
+
+use erased_serde::{Deserializer, Serialize as ErasedSerialize};
+use futures::future::FutureObj;
+use serde::ser::Error as SerdeSerError;
+use std::{convert::TryInto, io, net::SocketAddr, sync::RwLock};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::{
+    json,
+    stream::{self, Connections, StreamTheater},
+    ActorId, Error, Message, Theater, TheaterBox,
+};
+
+lazy_static! {
+    /// The address the local theater listens on
+    static ref LISTENING: RwLock<Option<SocketAddr>> = RwLock::new(None);
+
+    /// The connections to remote theaters
+    static ref CONNECTIONS: Connections<TcpTheater> = Connections::new();
+}
+
+/// A theater reachable over TCP, identified by the address it listens on
+///
+/// The local theater must first listen with [`TcpTheater::listen`], as the
+/// address it listens on is the one given to remote theaters. Connections
+/// are then opened on demand, each theater sending through its own.
+///
+/// When a remote theater connects, it announces the port it listens on, and
+/// is identified by this port and the address the connection comes from,
+/// once the local theater has checked, by connecting to the theater at this
+/// address, that it is the one connecting. Messages are encoded as JSON.
+///
+/// This requires running on a tokio runtime.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpTheater {
+    addr: SocketAddr,
+}
+
+impl Message for TcpTheater {
+    fn tag() -> &'static str {
+        "erlust::TcpTheater"
+    }
+}
+
+impl TcpTheater {
+    /// Designates the remote theater listening on `addr`
+    pub fn new(addr: SocketAddr) -> TcpTheater {
+        TcpTheater { addr }
+    }
+
+    /// Makes the local theater listen on `addr`, and returns it
+    ///
+    /// The address must be reachable by remote theaters, eg. it should not be
+    /// an unspecified address like `0.0.0.0`. This can only be called once.
+    pub async fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<TcpTheater> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        {
+            let mut listening = LISTENING.write().unwrap();
+            if listening.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "the local TcpTheater is already listening",
+                ));
+            }
+            *listening = Some(addr);
+        }
+        tokio::spawn(async move {
+            while let Ok((stream, peer)) = listener.accept().await {
+                tokio::spawn(accept(stream, peer));
+            }
+        });
+        Ok(TcpTheater { addr })
+    }
+
+    /// Returns the address this theater listens on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the number of records received from remote theaters that
+    /// could not be delivered to local actors, eg. because they terminated or
+    /// their mailbox was full
+    pub fn undelivered() -> u64 {
+        CONNECTIONS.undelivered()
+    }
+}
+
+impl StreamTheater for TcpTheater {
+    const CONNECT_BACK: bool = true;
+
+    type Stream = TcpStream;
+
+    fn connections() -> &'static Connections<TcpTheater> {
+        &CONNECTIONS
+    }
+
+    fn announce() -> io::Result<Vec<u8>> {
+        let addr = LISTENING.read().unwrap().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the local TcpTheater is not listening",
+            )
+        })?;
+        Ok(addr.port().to_be_bytes().to_vec())
+    }
+
+    async fn open(&self) -> io::Result<(TcpStream, TcpTheater)> {
+        Ok((TcpStream::connect(self.addr).await?, self.clone()))
+    }
+}
+
+/// Handles a connection opened by a remote theater
+async fn accept(stream: TcpStream, peer: SocketAddr) {
+    // Identify the theater from the connection, only trusting the port it
+    // announced
+    stream::accept(stream, |announced| {
+        let port = u16::from_be_bytes(announced.try_into().ok()?);
+        Some(TcpTheater {
+            addr: SocketAddr::new(peer.ip(), port),
+        })
+    })
+    .await
+}
+
+impl Theater for TcpTheater {
+    fn here(&mut self) -> Result<Box<Self>, Error> {
+        let addr = LISTENING
+            .read()
+            .unwrap()
+            .ok_or_else(|| Error::transport("the local TcpTheater is not listening"))?;
+        Ok(Box::new(TcpTheater { addr }))
+    }
+
+    fn sees_as(&mut self, other: Box<dyn TheaterBox>) -> Result<Box<Self>, Error> {
+        other.into_any().downcast().map_err(|_| {
+            Error::Serialization(SerdeSerError::custom(
+                "TcpTheater can only reach other TcpTheaters",
+            ))
+        })
+    }
+
+    fn encode(
+        &mut self,
+        msg: &dyn ErasedSerialize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), erased_serde::Error> {
+        json::encode(msg, out)
+    }
+
+    fn decode(
+        &mut self,
+        inp: &[u8],
+        visit: &mut dyn FnMut(&mut dyn Deserializer) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), erased_serde::Error> {
+        json::decode(inp, visit)
+    }
+
+    fn send(
+        &mut self,
+        from: ActorId,
+        to: ActorId,
+        tag: &'static str,
+        msg: Vec<u8>,
+    ) -> FutureObj<'_, Result<(), Error>> {
+        FutureObj::new(Box::pin(stream::send(self, from, to, tag, msg)))
+    }
+}
@@ -1,16 +1,21 @@
 //! Protocol for sending messages to remote actors
 
-use erased_serde::{Deserializer, Serializer};
+use erased_serde::{Deserializer, Serialize as ErasedSerialize};
 use futures::future::FutureObj;
 use serde::de::Error as SerdeDeError;
 use std::cell::RefCell;
 
-use crate::types::{ActorId, Message, MessageBox};
+use crate::{
+    signal::SIGNAL_TAG,
+    types::{ActorId, Message, MessageBox},
+    Error,
+};
 
 /// A bunch of actors and functions used for theaters to communicate between
 /// them
-// TODO: (A) provide an implementation of Theater
-pub trait Theater: Message + Clone {
+///
+/// Two theaters should compare equal iff they designate the same remote end.
+pub trait Theater: Message + Clone + PartialEq + Sync {
     /// Returns the local theater, as seen from the theater defined by `self`
     ///
     /// For instance, if the link between the local theater and `self` is a
@@ -22,7 +27,10 @@ pub trait Theater: Message + Clone {
     /// TCP-on-a-flat-network, then this function should return a
     /// theater that points to the address and port on which the local
     /// process is currently listening.
-    fn here(&mut self) -> Box<Self>;
+    ///
+    /// Fails with [`Error::transport`] if the local theater cannot currently
+    /// be reached from `self`, eg. because it is not listening.
+    fn here(&mut self) -> Result<Box<Self>, Error>;
 
     /// Returns an instance of `Self` that can be used by the remote end of
     /// this connection (ie. `self`) to communicate with `other` (which is
@@ -40,23 +48,34 @@ pub trait Theater: Message + Clone {
     /// setup a “bouncer” actor in the local theater, and then return a
     /// pointer to said local actor, which would then be charged with
     /// relaying messages to `other`
+    ///
+    /// Fails with [`Error::Serialization`] if `other` cannot be reached from
+    /// `self`, eg. because it is of a theater type `self` cannot relay to.
     // TODO: (A) this should take an actor and return an actor (cf. doc above)
-    fn sees_as(&mut self, other: Box<dyn TheaterBox>) -> Box<Self>;
+    fn sees_as(&mut self, other: Box<dyn TheaterBox>) -> Result<Box<Self>, Error>;
 
-    /// Returns a serializer to be used for generating the `msg` argument of
-    /// [`send`] out of a [`Message`]
+    /// Serializes `msg` into `out`, that will then be used as the `msg`
+    /// argument of [`send`]
     ///
-    /// `out` will be passed to [`send`] after serialization of said message.
-    // TODO: (B) return associated type?
-    fn serializer(&mut self, out: &mut Vec<u8>) -> Box<dyn Serializer>;
+    /// This is usually done by wrapping a serializer writing into `out` with
+    /// [`erased_serde::Serializer::erase`], and passing it to
+    /// `msg.erased_serialize`.
+    fn encode(
+        &mut self,
+        msg: &dyn ErasedSerialize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), erased_serde::Error>;
 
-    /// Returns a deserializer to be used for deserializing the `msg` argument
-    /// from [`inject`]
+    /// Calls `visit` with a deserializer for reading the `msg` argument from
+    /// [`inject`]
     ///
-    /// Usually, this will be the operation opposite to the one [`serializer`]
+    /// Usually, this will be the operation opposite to the one [`encode`]
     /// performed.
-    // TODO: (B) return associated type?
-    fn deserializer<'de>(&mut self, inp: &'de Vec<u8>) -> Box<dyn Deserializer<'de>>;
+    fn decode(
+        &mut self,
+        inp: &[u8],
+        visit: &mut dyn FnMut(&mut dyn Deserializer) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), erased_serde::Error>;
 
     /// Send a message to `self`
     ///
@@ -67,25 +86,47 @@ pub trait Theater: Message + Clone {
     /// Please note however that the security model assumes that the
     /// *receiving* side identifies the `from_theater`, not the *sending* side,
     /// so serializing `self.here()` would most likely be a bad idea.
+    ///
+    /// Failures of the underlying transport should be reported with
+    /// [`Error::transport`].
     // TODO: (B) return impl Trait h:impl-trait-in-trait
     // TODO: (A) make `tag` a `String`
-    // TODO: (A) `to` should be un-guessable, so that it acts as a capability
     fn send(
         &mut self,
         from: ActorId,
         to: ActorId,
         tag: &'static str,
         msg: Vec<u8>,
-    ) -> FutureObj<Result<(), failure::Error>>;
+    ) -> FutureObj<'_, Result<(), Error>>;
+
+    /// Send a signal to `self`
+    ///
+    /// Signals are used to implement links and monitors across theaters. This
+    /// should trigger a call to [`inject`] in the theater designed by `self`,
+    /// that will recognize it as a signal.
+    ///
+    /// The default implementation sends `signal` like a message with a
+    /// reserved tag, which is enough for [`inject`] to recognize it.
+    /// Theaters may override it, eg. to give signals a higher priority than
+    /// messages, as long as they give back the same reserved tag to
+    /// [`inject`].
+    fn send_signal(
+        &mut self,
+        from: ActorId,
+        to: ActorId,
+        signal: Vec<u8>,
+    ) -> FutureObj<'_, Result<(), Error>> {
+        self.send(from, to, SIGNAL_TAG, signal)
+    }
 }
 
 /// A [`Box`]-able [`Theater`]
 ///
 /// This trait is automatically implemented for all traits implementing
 /// [`Theater`].
-pub trait TheaterBox: MessageBox {
+pub trait TheaterBox: MessageBox + Sync {
     /// See [`Theater::here`]
-    fn here(&mut self) -> Box<dyn TheaterBox>;
+    fn here(&mut self) -> Result<Box<dyn TheaterBox>, Error>;
 
     /// Clones `self` into a [`Box`]
     fn clone_to_box(&self) -> Box<dyn TheaterBox>;
@@ -97,14 +138,25 @@ pub trait TheaterBox: MessageBox {
         inp: &mut dyn Deserializer,
     ) -> Result<Box<dyn TheaterBox>, erased_serde::Error>;
 
+    /// Checks whether `self` and `other` are equal, as per [`PartialEq`]
+    fn eq_box(&self, other: &dyn TheaterBox) -> bool;
+
     /// See [`Theater::sees_as`]
-    fn sees_as(&mut self, other: Box<dyn TheaterBox>) -> Box<dyn TheaterBox>;
+    fn sees_as(&mut self, other: Box<dyn TheaterBox>) -> Result<Box<dyn TheaterBox>, Error>;
 
-    /// See [`Theater::serializer`]
-    fn serializer(&mut self, out: &mut Vec<u8>) -> Box<dyn Serializer>;
+    /// See [`Theater::encode`]
+    fn encode(
+        &mut self,
+        msg: &dyn ErasedSerialize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), erased_serde::Error>;
 
-    /// See [`Theater::deserializer`]
-    fn deserializer<'de>(&mut self, inp: &'de Vec<u8>) -> Box<dyn Deserializer<'de>>;
+    /// See [`Theater::decode`]
+    fn decode(
+        &mut self,
+        inp: &[u8],
+        visit: &mut dyn FnMut(&mut dyn Deserializer) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), erased_serde::Error>;
 
     /// See [`Theater::send`]
     fn send(
@@ -113,7 +165,15 @@ pub trait TheaterBox: MessageBox {
         to: ActorId,
         tag: &'static str,
         msg: Vec<u8>,
-    ) -> FutureObj<Result<(), failure::Error>>;
+    ) -> FutureObj<'_, Result<(), Error>>;
+
+    /// See [`Theater::send_signal`]
+    fn send_signal(
+        &mut self,
+        from: ActorId,
+        to: ActorId,
+        signal: Vec<u8>,
+    ) -> FutureObj<'_, Result<(), Error>>;
 }
 
 // TODO: (B) use scoped_tls
@@ -126,8 +186,8 @@ thread_local! {
 }
 
 impl<T: Theater> TheaterBox for T {
-    fn here(&mut self) -> Box<dyn TheaterBox> {
-        <Self as Theater>::here(self)
+    fn here(&mut self) -> Result<Box<dyn TheaterBox>, Error> {
+        <Self as Theater>::here(self).map(|t| t as Box<dyn TheaterBox>)
     }
 
     fn clone_to_box(&self) -> Box<dyn TheaterBox> {
@@ -141,16 +201,31 @@ impl<T: Theater> TheaterBox for T {
         erased_serde::deserialize::<Box<Self>>(inp).map(|t| t as Box<dyn TheaterBox>)
     }
 
-    fn sees_as(&mut self, o: Box<dyn TheaterBox>) -> Box<dyn TheaterBox> {
-        <Self as Theater>::sees_as(self, o)
+    fn eq_box(&self, other: &dyn TheaterBox) -> bool {
+        other
+            .as_any()
+            .downcast_ref::<Self>()
+            .is_some_and(|other| self == other)
+    }
+
+    fn sees_as(&mut self, o: Box<dyn TheaterBox>) -> Result<Box<dyn TheaterBox>, Error> {
+        <Self as Theater>::sees_as(self, o).map(|t| t as Box<dyn TheaterBox>)
     }
 
-    fn serializer(&mut self, out: &mut Vec<u8>) -> Box<dyn Serializer> {
-        <Self as Theater>::serializer(self, out)
+    fn encode(
+        &mut self,
+        msg: &dyn ErasedSerialize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), erased_serde::Error> {
+        <Self as Theater>::encode(self, msg, out)
     }
 
-    fn deserializer<'de>(&mut self, inp: &'de Vec<u8>) -> Box<dyn Deserializer<'de>> {
-        <Self as Theater>::deserializer(self, inp)
+    fn decode(
+        &mut self,
+        inp: &[u8],
+        visit: &mut dyn FnMut(&mut dyn Deserializer) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), erased_serde::Error> {
+        <Self as Theater>::decode(self, inp, visit)
     }
 
     fn send(
@@ -159,9 +234,18 @@ impl<T: Theater> TheaterBox for T {
         to: ActorId,
         tag: &'static str,
         msg: Vec<u8>,
-    ) -> FutureObj<Result<(), failure::Error>> {
+    ) -> FutureObj<'_, Result<(), Error>> {
         <Self as Theater>::send(self, from, to, tag, msg)
     }
+
+    fn send_signal(
+        &mut self,
+        from: ActorId,
+        to: ActorId,
+        signal: Vec<u8>,
+    ) -> FutureObj<'_, Result<(), Error>> {
+        <Self as Theater>::send_signal(self, from, to, signal)
+    }
 }
 
 serialize_trait_object!(TheaterBox);
@@ -0,0 +1,42 @@
+//! Timers used by erlust, eg. for [`receive_timeout`](crate::receive_timeout)
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// A future that completes after a given duration
+///
+/// With the `tokio` feature, this uses the timer of the tokio runtime the
+/// actor is running in, if any.
+pub enum Delay {
+    Futures(futures_timer::Delay),
+    #[cfg(feature = "tokio")]
+    Tokio(Pin<Box<tokio::time::Sleep>>),
+}
+
+impl Delay {
+    pub fn new(duration: Duration) -> Delay {
+        #[cfg(feature = "tokio")]
+        {
+            if tokio::runtime::Handle::try_current().is_ok() {
+                return Delay::Tokio(Box::pin(tokio::time::sleep(duration)));
+            }
+        }
+        Delay::Futures(futures_timer::Delay::new(duration))
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        match self.get_mut() {
+            Delay::Futures(d) => Pin::new(d).poll(cx),
+            #[cfg(feature = "tokio")]
+            Delay::Tokio(d) => d.as_mut().poll(cx),
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! A [`Theater`] communicating over TCP with mutually-authenticated TLS,
+//! with the `tls` feature
+
+use erased_serde::{Deserializer, Serialize as ErasedSerialize};
+use futures::future::FutureObj;
+use serde::ser::Error as SerdeSerError;
+use std::{
+    convert::TryInto,
+    hash::{Hash, Hasher},
+    io,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio_rustls::{
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{ring, CryptoProvider},
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+        server::WebPkiClientVerifier,
+        CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig,
+        SignatureScheme,
+    },
+    TlsAcceptor, TlsConnector, TlsStream,
+};
+
+use crate::{
+    json,
+    stream::{self, Connections, StreamTheater},
+    ActorId, Error, Message, Theater, TheaterBox,
+};
+
+type Stream = TlsStream<TcpStream>;
+
+/// The local theater, and the configurations for talking with the others
+struct Listening {
+    here:      TlsTheater,
+    acceptor:  TlsAcceptor,
+    connector: TlsConnector,
+}
+
+lazy_static! {
+    static ref LISTENING: RwLock<Option<Arc<Listening>>> = RwLock::new(None);
+
+    /// The connections to remote theaters, by subject
+    static ref CONNECTIONS: Connections<TlsTheater> = Connections::new();
+}
+
+/// Returns the subject of `cert`, eg. `CN=theater-a, O=example`
+fn subject(cert: &CertificateDer) -> io::Result<String> {
+    let (_, cert) =
+        x509_parser::parse_x509_certificate(cert).map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(cert.subject().to_string())
+}
+
+/// Returns the subject of the certificate presented by the remote end of
+/// `stream`
+fn peer_subject(stream: &Stream) -> io::Result<String> {
+    let certs = match stream {
+        TlsStream::Client(s) => s.get_ref().1.peer_certificates(),
+        TlsStream::Server(s) => s.get_ref().1.peer_certificates(),
+    };
+    let cert = certs
+        .and_then(|c| c.first())
+        .ok_or_else(|| io::Error::other("the peer presented no certificate"))?;
+    subject(cert)
+}
+
+/// Verifies the certificates of remote theaters like webpki, except for the
+/// name they are valid for, as they are identified by their subject, that is
+/// checked once the connection is established
+#[derive(Debug)]
+struct SubjectVerifier(Arc<dyn ServerCertVerifier>);
+
+impl ServerCertVerifier for SubjectVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        intermediates: &[CertificateDer],
+        server_name: &ServerName,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName))
+            | Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForNameContext {
+                ..
+            })) => Ok(ServerCertVerified::assertion()),
+            res => res,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+/// A theater reachable over TLS, identified by the subject of its
+/// certificate
+///
+/// This works like [`TcpTheater`](crate::TcpTheater), except that the
+/// connections are authenticated in both directions: remote theaters must
+/// present a certificate signed by one of the roots given to
+/// [`TlsTheater::listen`], and are identified by its subject, whatever the
+/// address they connect from. The address of a `TlsTheater` is only used to
+/// reach it, and the theater found there must present a certificate with the
+/// expected subject.
+///
+/// This requires running on a tokio runtime.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsTheater {
+    subject: String,
+    addr:    SocketAddr,
+}
+
+impl PartialEq for TlsTheater {
+    fn eq(&self, other: &TlsTheater) -> bool {
+        self.subject == other.subject
+    }
+}
+
+impl Eq for TlsTheater {}
+
+impl Hash for TlsTheater {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.subject.hash(state)
+    }
+}
+
+impl Message for TlsTheater {
+    fn tag() -> &'static str {
+        "erlust::TlsTheater"
+    }
+}
+
+impl TlsTheater {
+    /// Designates the remote theater whose certificate has subject
+    /// `subject`, listening on `addr`
+    pub fn new(subject: &str, addr: SocketAddr) -> TlsTheater {
+        TlsTheater {
+            subject: String::from(subject),
+            addr,
+        }
+    }
+
+    /// Makes the local theater listen on `addr`, and returns it
+    ///
+    /// `cert_chain` and `key` are the certificate presented to remote
+    /// theaters, whose subject identifies the local theater, and `roots` are
+    /// the certificates remote theaters must be signed by. The address must
+    /// be reachable by remote theaters, eg. it should not be an unspecified
+    /// address like `0.0.0.0`. This can only be called once.
+    pub async fn listen<A: ToSocketAddrs>(
+        addr: A,
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+        roots: RootCertStore,
+    ) -> io::Result<TlsTheater> {
+        let subject = subject(
+            cert_chain
+                .first()
+                .ok_or_else(|| io::Error::other("empty certificate chain"))?,
+        )?;
+        let provider = Arc::new(ring::default_provider());
+        let roots = Arc::new(roots);
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(
+            &provider,
+            &roots,
+            cert_chain.clone(),
+            key.clone_key(),
+        )?));
+        let connector =
+            TlsConnector::from(Arc::new(client_config(&provider, &roots, cert_chain, key)?));
+
+        let listener = TcpListener::bind(addr).await?;
+        let here = TlsTheater {
+            subject,
+            addr: listener.local_addr()?,
+        };
+        let listening = Arc::new(Listening {
+            here: here.clone(),
+            acceptor,
+            connector,
+        });
+        {
+            let mut l = LISTENING.write().unwrap();
+            if l.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "the local TlsTheater is already listening",
+                ));
+            }
+            *l = Some(listening.clone());
+        }
+        tokio::spawn(async move {
+            while let Ok((stream, peer)) = listener.accept().await {
+                tokio::spawn(accept(listening.clone(), stream, peer));
+            }
+        });
+        Ok(here)
+    }
+
+    /// Returns the subject of the certificate of this theater
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// Returns the address this theater listens on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the number of records received from remote theaters that
+    /// could not be delivered to local actors, eg. because they terminated or
+    /// their mailbox was full
+    pub fn undelivered() -> u64 {
+        CONNECTIONS.undelivered()
+    }
+}
+
+/// Returns the local theater, and the configurations for talking with the
+/// others
+fn listening() -> io::Result<Arc<Listening>> {
+    LISTENING.read().unwrap().clone().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotConnected,
+            "the local TlsTheater is not listening",
+        )
+    })
+}
+
+impl StreamTheater for TlsTheater {
+    const CONNECT_BACK: bool = false;
+
+    type Stream = Stream;
+
+    fn connections() -> &'static Connections<TlsTheater> {
+        &CONNECTIONS
+    }
+
+    fn announce() -> io::Result<Vec<u8>> {
+        Ok(listening()?.here.addr.port().to_be_bytes().to_vec())
+    }
+
+    async fn open(&self) -> io::Result<(Stream, TlsTheater)> {
+        let listening = listening()?;
+        let stream = TcpStream::connect(self.addr).await?;
+        let stream = listening
+            .connector
+            .connect(ServerName::IpAddress(self.addr.ip().into()), stream)
+            .await?;
+        let stream = TlsStream::Client(stream);
+        if peer_subject(&stream)? != self.subject {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the remote TlsTheater has an unexpected subject",
+            ));
+        }
+        Ok((stream, self.clone()))
+    }
+}
+
+fn server_config(
+    provider: &Arc<CryptoProvider>,
+    roots: &Arc<RootCertStore>,
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> io::Result<ServerConfig> {
+    let verifier = WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone())
+        .build()
+        .map_err(io::Error::other)?;
+    ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(cert_chain, key)
+        .map_err(io::Error::other)
+}
+
+fn client_config(
+    provider: &Arc<CryptoProvider>,
+    roots: &Arc<RootCertStore>,
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> io::Result<ClientConfig> {
+    let verifier = rustls::client::WebPkiServerVerifier::builder_with_provider(
+        roots.clone(),
+        provider.clone(),
+    )
+    .build()
+    .map_err(io::Error::other)?;
+    ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SubjectVerifier(verifier)))
+        .with_client_auth_cert(cert_chain, key)
+        .map_err(io::Error::other)
+}
+
+/// Handles a connection opened by a remote theater
+async fn accept(listening: Arc<Listening>, stream: TcpStream, peer: SocketAddr) {
+    let stream = match listening.acceptor.accept(stream).await {
+        Ok(stream) => TlsStream::Server(stream),
+        Err(_) => return,
+    };
+    // Identify the theater from its certificate, only trusting the port it
+    // announced for reaching it
+    let subject = match peer_subject(&stream) {
+        Ok(subject) => subject,
+        Err(_) => return,
+    };
+    stream::accept(stream, |announced| {
+        let port = u16::from_be_bytes(announced.try_into().ok()?);
+        Some(TlsTheater {
+            subject,
+            addr: SocketAddr::new(peer.ip(), port),
+        })
+    })
+    .await
+}
+
+impl Theater for TlsTheater {
+    fn here(&mut self) -> Result<Box<Self>, Error> {
+        let listening = LISTENING.read().unwrap();
+        let listening = listening
+            .as_ref()
+            .ok_or_else(|| Error::transport("the local TlsTheater is not listening"))?;
+        Ok(Box::new(listening.here.clone()))
+    }
+
+    fn sees_as(&mut self, other: Box<dyn TheaterBox>) -> Result<Box<Self>, Error> {
+        other.into_any().downcast().map_err(|_| {
+            Error::Serialization(SerdeSerError::custom(
+                "TlsTheater can only reach other TlsTheaters",
+            ))
+        })
+    }
+
+    fn encode(
+        &mut self,
+        msg: &dyn ErasedSerialize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), erased_serde::Error> {
+        json::encode(msg, out)
+    }
+
+    fn decode(
+        &mut self,
+        inp: &[u8],
+        visit: &mut dyn FnMut(&mut dyn Deserializer) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), erased_serde::Error> {
+        json::decode(inp, visit)
+    }
+
+    fn send(
+        &mut self,
+        from: ActorId,
+        to: ActorId,
+        tag: &'static str,
+        msg: Vec<u8>,
+    ) -> FutureObj<'_, Result<(), Error>> {
+        FutureObj::new(Box::pin(stream::send(self, from, to, tag, msg)))
+    }
+}
@@ -0,0 +1,133 @@
+//! Addresses of actors that only accept the messages of a protocol, see
+//! [`TypedPid`]
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{marker::PhantomData, time::Duration};
+
+use crate::{CallError, Error, Message, Pid};
+
+/// Declares that protocol `Self` accepts messages of type `M`
+///
+/// A protocol is usually an empty enum, that lists the messages it accepts
+/// by implementing this trait once for each of them, and the requests it
+/// answers with [`Answers`].
+pub trait Accepts<M: Message> {}
+
+/// Declares that protocol `Self` answers requests of type `Req`, made with
+/// [`TypedPid::call`], with replies of type `Reply`
+///
+/// [`GenServer`](crate::GenServer)s answer their
+/// [`Call`](crate::GenServer::Call)s this way.
+pub trait Answers<Req: Message> {
+    type Reply: Message;
+}
+
+/// The address of an actor that accepts the messages of protocol `P`
+///
+/// Contrary to [`Pid::send`], [`TypedPid::send`] only compiles for messages
+/// `P` [`Accepts`], and [`TypedPid::call`] for requests `P` [`Answers`]. It
+/// is serialized like a [`Pid`], and converts to one with [`From`] for use
+/// with the untyped API.
+///
+/// ```no_run
+/// use erlust::{Accepts, Exit, TypedPid};
+///
+/// enum Protocol {}
+///
+/// impl Accepts<Exit> for Protocol {}
+///
+/// async fn forward(mut pid: TypedPid<Protocol>, exit: Exit) {
+///     pid.send(Box::new(exit)).await.unwrap();
+/// }
+/// ```
+///
+/// Sending a message the protocol does not accept does not compile:
+///
+/// ```compile_fail
+/// use erlust::{Accepts, Down, Exit, TypedPid};
+///
+/// enum Protocol {}
+///
+/// impl Accepts<Exit> for Protocol {}
+///
+/// async fn forward(mut pid: TypedPid<Protocol>, down: Down) {
+///     pid.send(Box::new(down)).await.unwrap();
+/// }
+/// ```
+pub struct TypedPid<P> {
+    pid:     Pid,
+    phantom: PhantomData<fn() -> P>,
+}
+
+impl<P> TypedPid<P> {
+    /// Wraps `pid`, that must be an actor accepting the messages of `P`
+    ///
+    /// Messages sent to an actor that does not actually expect them are
+    /// never received.
+    pub fn from_pid(pid: Pid) -> TypedPid<P> {
+        TypedPid {
+            pid,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the untyped address of the actor
+    pub fn pid(&self) -> &Pid {
+        &self.pid
+    }
+
+    /// Sends `msg` to the actor
+    ///
+    /// See [`Pid::send`].
+    pub async fn send<M>(&mut self, msg: Box<M>) -> Result<(), Error>
+    where
+        M: Message,
+        P: Accepts<M>,
+    {
+        self.pid.send(msg).await
+    }
+
+    /// Makes a call to the actor, and waits for the reply for at most
+    /// `timeout`
+    ///
+    /// See [`Pid::call`].
+    pub async fn call<Req>(&self, request: Req, timeout: Duration) -> Result<P::Reply, CallError>
+    where
+        Req: Message,
+        P: Answers<Req>,
+    {
+        self.pid.call(request, timeout).await
+    }
+}
+
+impl<P> Clone for TypedPid<P> {
+    fn clone(&self) -> TypedPid<P> {
+        TypedPid::from_pid(self.pid.clone())
+    }
+}
+
+impl<P> PartialEq for TypedPid<P> {
+    fn eq(&self, other: &TypedPid<P>) -> bool {
+        self.pid == other.pid
+    }
+}
+
+impl<P> Eq for TypedPid<P> {}
+
+impl<P> From<TypedPid<P>> for Pid {
+    fn from(pid: TypedPid<P>) -> Pid {
+        pid.pid
+    }
+}
+
+impl<P> Serialize for TypedPid<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.pid.serialize(serializer)
+    }
+}
+
+impl<'de, P> Deserialize<'de> for TypedPid<P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<TypedPid<P>, D::Error> {
+        Pid::deserialize(deserializer).map(TypedPid::from_pid)
+    }
+}
@@ -1,10 +1,14 @@
-use futures::channel::mpsc;
+use futures::{channel::mpsc, stream};
 use serde::Deserialize;
 use std::any::Any;
 
-use crate::Pid;
+use crate::{signal::Signal, Pid};
 
-pub type ActorId = usize;
+/// Identifier of an actor in its theater
+///
+/// It is random, so that knowing the [`ActorId`] of an actor is what allows
+/// remote theaters to send messages to it.
+pub type ActorId = u128;
 
 // Warning: the Deserialize implementation should be implemented
 // in such a way that it fails if anything looks fishy in the message.
@@ -43,8 +47,27 @@ pub enum ReceivedMessage {
     Remote((Pid, RemoteMessage)),
 }
 
+impl ReceivedMessage {
+    /// Extracts the sender and the message as an `M`, giving the message back
+    /// if it is of another type
+    pub fn downcast<M: Message>(self) -> Result<(Pid, Box<M>), ReceivedMessage> {
+        crate::receive::downcast(self)
+    }
+}
+
 pub type LocalSender = mpsc::Sender<ReceivedMessage>;
-pub type LocalReceiver = mpsc::Receiver<ReceivedMessage>;
+
+/// Sender for messages generated by erlust itself (like [`Exit`](crate::Exit)),
+/// that must not be subject to backpressure
+pub type SystemSender = mpsc::UnboundedSender<ReceivedMessage>;
+pub type SystemReceiver = mpsc::UnboundedReceiver<ReceivedMessage>;
+
+/// The actor's mailbox, merging messages from other actors with system
+/// messages
+pub type LocalReceiver = stream::Select<mpsc::Receiver<ReceivedMessage>, SystemReceiver>;
+
+pub type SignalSender = mpsc::UnboundedSender<Signal>;
+pub type SignalReceiver = mpsc::UnboundedReceiver<Signal>;
 
 impl Message for () {
     // TODO: (A) remove impl Message for ()
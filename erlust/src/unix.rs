@@ -0,0 +1,290 @@
+//! A [`Theater`] communicating over Unix domain sockets, with the `unix`
+//! feature
+
+use erased_serde::{Deserializer, Serialize as ErasedSerialize};
+use futures::future::FutureObj;
+use serde::ser::Error as SerdeSerError;
+use std::{
+    ffi::OsStr,
+    io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tokio::net::{
+    unix::{gid_t, uid_t, UCred},
+    UnixListener, UnixStream,
+};
+
+use crate::{
+    json,
+    stream::{self, Connections, StreamTheater},
+    ActorId, Error, Message, Theater, TheaterBox,
+};
+
+/// Policy deciding which peers the local theater talks with
+type Authorize = Arc<dyn Fn(&UCred) -> bool + Send + Sync>;
+
+lazy_static! {
+    /// The local theater, and its policy for peers
+    static ref LISTENING: RwLock<Option<(UnixTheater, Authorize)>> = RwLock::new(None);
+
+    /// The connections to remote theaters
+    static ref CONNECTIONS: Connections<UnixTheater> = Connections::new();
+}
+
+/// A theater reachable over a Unix domain socket, identified by the path of
+/// the socket it listens on and the credentials of the process listening
+/// there
+///
+/// This is meant for multiple processes running on the same host. The local
+/// theater must first listen with [`UnixTheater::listen`], as the path it
+/// listens on is the one given to remote theaters. Connections are then
+/// opened on demand, each theater sending through its own.
+///
+/// Peers are authenticated with their credentials (`SO_PEERCRED`), that
+/// must be accepted by the policy given when listening. When a remote
+/// theater connects, it announces the path it listens on, and is only
+/// accepted once the local theater has checked, by connecting to the socket
+/// at this path, that the theater listening there is the one connecting.
+/// It is then identified by this path and its credentials, so that
+/// processes with different credentials are never mistaken for one another.
+/// Messages are encoded as JSON.
+///
+/// This requires running on a tokio runtime.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnixTheater {
+    path: PathBuf,
+
+    /// The credentials of the process listening on `path`, if known, that
+    /// connections are then restricted to
+    cred: Option<Cred>,
+}
+
+/// The credentials of a process, as given by `SO_PEERCRED`
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Cred {
+    uid: uid_t,
+    gid: gid_t,
+}
+
+impl From<UCred> for Cred {
+    fn from(cred: UCred) -> Cred {
+        Cred {
+            uid: cred.uid(),
+            gid: cred.gid(),
+        }
+    }
+}
+
+impl Message for UnixTheater {
+    fn tag() -> &'static str {
+        "erlust::UnixTheater"
+    }
+}
+
+impl UnixTheater {
+    /// Designates the remote theater listening on `path`, whatever its
+    /// credentials
+    ///
+    /// Relative paths are resolved against the current directory. This is
+    /// not the same theater as the one remote theaters designate, or local
+    /// actors receive messages from, as those also carry its credentials.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<UnixTheater> {
+        Ok(UnixTheater {
+            path: std::env::current_dir()?.join(path),
+            cred: None,
+        })
+    }
+
+    /// Makes the local theater listen on `path`, and returns it
+    ///
+    /// Only theaters run by the same user as the local theater are allowed.
+    /// This can only be called once, from a tokio runtime.
+    pub fn listen<P: AsRef<Path>>(path: P) -> io::Result<UnixTheater> {
+        UnixTheater::listen_inner(path.as_ref(), |me| {
+            Arc::new(move |cred: &UCred| cred.uid() == me.uid)
+        })
+    }
+
+    /// Makes the local theater listen on `path`, and returns it
+    ///
+    /// Only theaters whose credentials are accepted by `authorize` are
+    /// allowed. This can only be called once, from a tokio runtime.
+    pub fn listen_with<P, F>(path: P, authorize: F) -> io::Result<UnixTheater>
+    where
+        P: AsRef<Path>,
+        F: 'static + Send + Sync + Fn(&UCred) -> bool,
+    {
+        UnixTheater::listen_inner(path.as_ref(), |_| Arc::new(authorize))
+    }
+
+    /// Binds `path`, builds the policy with `authorize` from the credentials
+    /// of the local theater, and starts accepting connections
+    fn listen_inner<F>(path: &Path, authorize: F) -> io::Result<UnixTheater>
+    where
+        F: FnOnce(Cred) -> Authorize,
+    {
+        // The peer of a socket pair is the current process
+        let (me, _) = UnixStream::pair()?;
+        let cred = Cred::from(me.peer_cred()?);
+        let here = UnixTheater {
+            path: std::env::current_dir()?.join(path),
+            cred: Some(cred),
+        };
+        let (listener, authorize) = {
+            let mut listening = LISTENING.write().unwrap();
+            if listening.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "the local UnixTheater is already listening",
+                ));
+            }
+            let listener = UnixListener::bind(&here.path)?;
+            let authorize = authorize(cred);
+            *listening = Some((here.clone(), authorize.clone()));
+            (listener, authorize)
+        };
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(accept(stream, authorize.clone()));
+            }
+        });
+        Ok(here)
+    }
+
+    /// Returns the path of the socket this theater listens on
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the number of records received from remote theaters that
+    /// could not be delivered to local actors, eg. because they terminated or
+    /// their mailbox was full
+    pub fn undelivered() -> u64 {
+        CONNECTIONS.undelivered()
+    }
+
+    /// Returns the user and group ids of the process listening on the
+    /// socket, if known
+    pub fn credentials(&self) -> Option<(uid_t, gid_t)> {
+        self.cred.map(|cred| (cred.uid, cred.gid))
+    }
+}
+
+/// Returns the policy of the local theater for peers
+fn authorize() -> io::Result<Authorize> {
+    let listening = LISTENING.read().unwrap();
+    let (_, authorize) = listening.as_ref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotConnected,
+            "the local UnixTheater is not listening",
+        )
+    })?;
+    Ok(authorize.clone())
+}
+
+impl StreamTheater for UnixTheater {
+    const CONNECT_BACK: bool = true;
+
+    type Stream = UnixStream;
+
+    fn connections() -> &'static Connections<UnixTheater> {
+        &CONNECTIONS
+    }
+
+    fn announce() -> io::Result<Vec<u8>> {
+        let listening = LISTENING.read().unwrap();
+        let (here, _) = listening.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the local UnixTheater is not listening",
+            )
+        })?;
+        Ok(here.path.as_os_str().as_bytes().to_vec())
+    }
+
+    async fn open(&self) -> io::Result<(UnixStream, UnixTheater)> {
+        let authorize = authorize()?;
+        let stream = UnixStream::connect(&self.path).await?;
+        let cred = stream.peer_cred()?;
+        if !authorize(&cred) || self.cred.is_some_and(|c| c != Cred::from(cred)) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the remote UnixTheater is not authorized",
+            ));
+        }
+        let peer = UnixTheater {
+            path: self.path.clone(),
+            cred: Some(Cred::from(cred)),
+        };
+        Ok((stream, peer))
+    }
+}
+
+/// Handles a connection opened by a remote theater
+async fn accept(stream: UnixStream, authorize: Authorize) {
+    let cred = match stream.peer_cred() {
+        Ok(cred) if authorize(&cred) => Cred::from(cred),
+        _ => return,
+    };
+    // Identify the theater from its credentials and the path it announced,
+    // that is verified by connecting back to it
+    stream::accept(stream, |announced| {
+        let path = PathBuf::from(OsStr::from_bytes(announced));
+        match path.is_absolute() {
+            true => Some(UnixTheater {
+                path,
+                cred: Some(cred),
+            }),
+            false => None,
+        }
+    })
+    .await
+}
+
+impl Theater for UnixTheater {
+    fn here(&mut self) -> Result<Box<Self>, Error> {
+        let listening = LISTENING.read().unwrap();
+        let (here, _) = listening
+            .as_ref()
+            .ok_or_else(|| Error::transport("the local UnixTheater is not listening"))?;
+        Ok(Box::new(here.clone()))
+    }
+
+    fn sees_as(&mut self, other: Box<dyn TheaterBox>) -> Result<Box<Self>, Error> {
+        other.into_any().downcast().map_err(|_| {
+            Error::Serialization(SerdeSerError::custom(
+                "UnixTheater can only reach other UnixTheaters",
+            ))
+        })
+    }
+
+    fn encode(
+        &mut self,
+        msg: &dyn ErasedSerialize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), erased_serde::Error> {
+        json::encode(msg, out)
+    }
+
+    fn decode(
+        &mut self,
+        inp: &[u8],
+        visit: &mut dyn FnMut(&mut dyn Deserializer) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), erased_serde::Error> {
+        json::decode(inp, visit)
+    }
+
+    fn send(
+        &mut self,
+        from: ActorId,
+        to: ActorId,
+        tag: &'static str,
+        msg: Vec<u8>,
+    ) -> FutureObj<'_, Result<(), Error>> {
+        FutureObj::new(Box::pin(stream::send(self, from, to, tag, msg)))
+    }
+}
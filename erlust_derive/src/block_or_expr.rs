@@ -5,7 +5,7 @@ use syn::{Block, Expr};
 #[derive(Clone)]
 pub enum BlockOrExpr {
     Block(Block),
-    Expr(Expr),
+    Expr(Box<Expr>),
 }
 
 impl ToTokens for BlockOrExpr {
@@ -29,13 +29,13 @@ pub fn derive_message(input: TokenStream) -> TokenStream {
                 }
             }
         };
-        res.into()
+        res
     } else {
         s.ident
             .span()
             .unstable()
             .error("Missing `erlust_tag` attribute")
             .emit();
-        return TokenStream::new();
+        TokenStream::new()
     }
 }
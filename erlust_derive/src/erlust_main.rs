@@ -0,0 +1,42 @@
+use proc_macro2::TokenStream;
+use syn::ItemFn;
+
+// Being given:
+//
+//  #[erlust::main]
+//  async fn main() -> T {
+//      body
+//  }
+//
+// Expands to:
+//
+//  fn main() -> T {
+//      ::erlust::__run_main(async move { body })
+//  }
+pub fn erlust_main(input: TokenStream) -> TokenStream {
+    let f = match syn::parse2::<ItemFn>(input) {
+        Ok(f) => f,
+        Err(e) => {
+            return syn::Error::new(e.span(), "#[erlust::main] must be used on a function")
+                .to_compile_error()
+        }
+    };
+    if f.sig.asyncness.is_none() {
+        return syn::Error::new_spanned(f.sig.fn_token, "#[erlust::main] functions must be async")
+            .to_compile_error();
+    }
+
+    let ItemFn {
+        attrs,
+        vis,
+        mut sig,
+        block,
+    } = f;
+    sig.asyncness = None;
+    quote! {
+        #(#attrs)*
+        #vis #sig {
+            ::erlust::__run_main(async move #block)
+        }
+    }
+}
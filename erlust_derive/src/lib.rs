@@ -9,6 +9,7 @@ extern crate syn;
 
 mod block_or_expr;
 mod derive_message;
+mod erlust_main;
 mod pat_ignorer;
 mod receive;
 
@@ -19,6 +20,14 @@ pub fn derive_message_macro(input: TokenStream) -> TokenStream {
     derive_message::derive_message(input.into()).into()
 }
 
+/// Runs an `async fn` as the root actor of a new tokio runtime
+///
+/// Requires the `tokio` feature of erlust.
+#[proc_macro_attribute]
+pub fn main(_args: TokenStream, input: TokenStream) -> TokenStream {
+    erlust_main::erlust_main(input.into()).into()
+}
+
 #[proc_macro]
 pub fn receive(input: TokenStream) -> TokenStream {
     receive::receive(input.into()).into()
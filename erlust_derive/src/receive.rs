@@ -41,7 +41,7 @@ impl Parse for ReceiveArm {
         } else {
             let res = input.parse()?;
             let _: Token![,] = input.parse()?;
-            BlockOrExpr::Expr(res)
+            BlockOrExpr::Expr(Box::new(res))
         };
         Ok(ReceiveArm {
             ty,
@@ -52,17 +52,68 @@ impl Parse for ReceiveArm {
     }
 }
 
+#[derive(Clone)]
+struct AfterArm {
+    timeout: Expr,
+    body:    BlockOrExpr,
+}
+
+impl Parse for AfterArm {
+    fn parse(input: ParseStream) -> syn::parse::Result<Self> {
+        // after timeout => body
+        let after: Ident = input.parse()?;
+        if after != "after" {
+            return Err(syn::Error::new(after.span(), "expected `after`"));
+        }
+        let timeout = input.parse()?;
+        let _: Token![=>] = input.parse()?;
+        let body = if input.peek(syn::token::Brace) {
+            let res = input.parse()?;
+            if input.peek(Token![,]) {
+                let _: Token![,] = input.parse()?;
+            }
+            BlockOrExpr::Block(res)
+        } else {
+            let res = input.parse()?;
+            if !input.is_empty() {
+                let _: Token![,] = input.parse()?;
+            }
+            BlockOrExpr::Expr(Box::new(res))
+        };
+        Ok(AfterArm { timeout, body })
+    }
+}
+
+/// Checks whether `input` starts with an `after` arm, rather than with an arm
+/// matching a type named `after`
+fn peek_after(input: ParseStream) -> bool {
+    let fork = input.fork();
+    match fork.parse::<Ident>() {
+        Ok(ident) => ident == "after" && !fork.peek(Token![:]) && !fork.peek(Token![::]),
+        Err(_) => false,
+    }
+}
+
 struct Receive {
-    arms: Vec<ReceiveArm>,
+    arms:  Vec<ReceiveArm>,
+    after: Option<AfterArm>,
 }
 
 impl Parse for Receive {
     fn parse(input: ParseStream) -> syn::parse::Result<Self> {
         let mut arms = Vec::new();
+        let mut after = None;
         while !input.is_empty() {
-            arms.push(input.parse()?);
+            if peek_after(input) {
+                after = Some(input.parse()?);
+                if !input.is_empty() {
+                    return Err(input.error("the `after` arm must be the last one"));
+                }
+            } else {
+                arms.push(input.parse()?);
+            }
         }
-        Ok(Receive { arms })
+        Ok(Receive { arms, after })
     }
 }
 
@@ -74,20 +125,22 @@ fn gen_arm_ident(i: usize) -> Ident {
 fn gen_local_match(i: usize, ty: Type, pat: Pat, guard: TokenStream) -> TokenStream {
     let arm_name = gen_arm_ident(i);
     quote! {
-        if msg.as_any().is::<#ty>() {
-            msg = match msg.into_any().downcast::<#ty>() {
-                Ok(msg) => {
-                    let matches = match (&from, &*msg) {
-                        #pat #guard => true,
-                        _ => false,
-                    };
-                    if matches {
-                        return ::erlust::ReceiveResult::Use(MatchedArm::#arm_name((from, msg)));
-                    }
-                    msg as ::erlust::LocalMessage
-                },
-                Err(msg) => unreachable!(), // TODO: (B) unreachable_unchecked()?
+        if let Some(msg) = msg.as_any().downcast_ref::<#ty>() {
+            let matches = match (from, msg) {
+                #pat #guard => true,
+                _ => false,
             };
+            if matches {
+                let (from, msg) = match slot.take() {
+                    Some(::erlust::ReceivedMessage::Local((from, msg))) => (from, msg),
+                    _ => unreachable!(),
+                };
+                let msg = match msg.into_any().downcast::<#ty>() {
+                    Ok(msg) => msg,
+                    Err(_) => unreachable!(), // TODO: (B) unreachable_unchecked()?
+                };
+                return ::erlust::ReceiveResult::Use(MatchedArm::#arm_name((from, msg)));
+            }
         }
     }
 }
@@ -96,18 +149,16 @@ fn gen_remote_match(i: usize, ty: Type, pat: Pat, guard: TokenStream) -> TokenSt
     let arm_name = gen_arm_ident(i);
     quote! {
         if m.tag == <#ty as ::erlust::Message>::tag() {
-            let mut deserializer = from.__theater_assert_remote().deserializer(&m.msg);
-            match ::erased_serde::deserialize::<Box<#ty>>(&mut deserializer) {
-                Ok(msg) => {
-                    let matches = match (&from, &*msg) {
-                        #pat #guard => true,
-                        _ => false,
-                    };
-                    if matches {
-                        return ::erlust::ReceiveResult::Use(MatchedArm::#arm_name((from, msg)));
-                    }
+            if let Ok(msg) = ::erlust::__deserialize_remote::<#ty>(from, &m.msg) {
+                let matches = match (from, &*msg) {
+                    #pat #guard => true,
+                    _ => false,
+                };
+                if matches {
+                    let from = from.clone();
+                    *slot = None;
+                    return ::erlust::ReceiveResult::Use(MatchedArm::#arm_name((from, msg)));
                 }
-                _ => (),
             }
         }
     }
@@ -123,144 +174,78 @@ fn gen_execute_match_arm(i: usize, pat: Pat, body: BlockOrExpr) -> TokenStream {
     }
 }
 
-// TODO: (A) handle timeout
-
 // TODO: (A) make tuples and base types implement Message?
 // TODO: (B) think of the compatibility-with-old-messages story
 // Being given:
 //
 //  receive! {
 //      (usize, String): (_pid, (1, ref x)) if foo(x) => bar(x),
-//      (usize, String): (_pid, (2, x)) => foobar(x),
-//      usize: (_pid, x) if baz(x) => quux(x),
+//      usize: (_pid, x) => quux(x),
+//      after Duration::from_secs(1) => timed_out(),
 //  }
 //
-// With types:
-//  * `foo`:    `Fn(&String) -> bool`
-//  * `bar`:    `Fn(&String) -> T`
-//  * `foobar`: `Fn(String) -> T`
-//  * `baz`:    `Fn(usize) -> bool`
-//  * `quux`:   `Fn(usize) -> T`
-//
 // Expands to:
 //
 //  enum MatchedArm {
-//      Arm1((Pid, Box<(usize, String)>)),
-//      Arm2((Pid, Box<(usize, String)>)),
-//      Arm3((Pid, Box<usize>)),
+//      Arm0((Pid, Box<(usize, String)>)),
+//      Arm1((Pid, Box<usize>)),
 //  }
-//  match await!(receive(async move |mut msg: ReceivedMessage| {
-//      match msg {
+//  match receive_timeout(async move |slot: &mut Option<ReceivedMessage>| {
+//      match slot.as_ref().unwrap() {
 //          ReceivedMessage::Local((from, msg)) => {
-//              msg = match msg.downcast::<(usize, String)>() {
-//                  Ok(res) => {
-//          [has match guard, thus cannot move, thus mutable borrow]
-//                      let matches = match &mut *res {
-//                          &mut (1, ref x) if foo(x) => true,
-//                          _ => false,
-//                      };
-//                      if matches {
-//                          return Use(Arm1((from, res)));
-//                      }
-//                      res as LocalMessage
-//                  },
-//                  Err(res) => res,
-//              };
-//              msg = match msg.downcast::<(usize, String)>() {
-//                  Ok(res) => {
-//          [has no match guard, thus can move, but we can just ignore it here]
-//                      let matches = match &*res {
-//                          &(2, _) => true,
-//                          _ => false,
-//                      };
-//                      if matches {
-//                          return Use(Arm2((from, res)));
-//                      }
-//                      res as LocalMessage
-//                  },
-//                  Err(b) => b,
-//              };
-//              msg = match msg.downcast::<usize>() {
-//                  Ok(res) => {
-//          [has a match guard, thus cannot move, thus mutable borrow]
-//                      let matches = match &mut *res {
-//                          &mut x if baz(x) => true,
-//                          _ => false,
-//                      };
-//                      if matches {
-//                          return Use(Arm3((from, res)));
-//                      }
-//                      res as LocalMessage
-//                  },
-//                  Err(b) => b,
-//              };
-//              Skip(ReceivedMessage::Local((from, msg)))
-//          },
-//          ReceivedMessage::Remote((from, m)) => {
-//              if m.tag == <(usize, String) as Message>::tag() {
-//                  match ::erased_serde::deserialize::<Box<(usize, String)>>(&m.msg) {
-//                      Ok(msg) => {
-//                          let matches = match &mut *msg {
-//                              &mut (1, ref x) if foo(x) => true,
-//                              _ => false,
-//                          };
-//                          if matches {
-//                              return Use(Arm1((from, msg)));
-//                          }
-//                      }
-//                      _ => (),
+//              if let Some(msg) = msg.as_any().downcast_ref::<(usize, String)>() {
+//                  let matches = match (from, msg) {
+//                      (_pid, (1, ref x)) if foo(x) => true,
+//                      _ => false,
+//                  };
+//                  if matches {
+//          [take the message out of `slot`, so that it is not kept in the mailbox]
+//                      let (from, msg) = match slot.take() { ... };
+//                      return Use(Arm0((from, msg.into_any().downcast().unwrap())));
 //                  }
 //              }
+//              [same for usize, with the pattern's bindings replaced by `_`
+//               as there is no guard]
+//          }
+//          ReceivedMessage::Remote((from, m)) => {
 //              if m.tag == <(usize, String) as Message>::tag() {
-//                  match ::erased_serde::deserialize::<Box<(usize, String)>>(&m.msg) {
-//                      Ok(msg) => {
-//                          let matches = match &mut *msg {
-//                              &mut (2, _) => true,
-//                              _ => false,
-//                          };
-//                          if matches {
-//                              return Use(Arm2((from, msg)));
-//                          }
-//                      }
-//                      _ => (),
-//                  }
-//              }
-//              if m.tag == <usize as Message>::tag() {
-//                  match ::erased_serde::deserialize::<Box<usize>>(&m.msg) {
-//                      Ok(msg) => {
-//                          let matches = match &mut *msg {
-//                              &mut x if baz(x) => true,
-//                              _ => false,
-//                          };
-//                          if matches {
-//                              return Use(Arm3((from, msg)));
-//                          }
+//                  if let Ok(msg) = __deserialize_remote::<(usize, String)>(from, &m.msg) {
+//                      let matches = match (from, &*msg) {
+//                          (_pid, (1, ref x)) if foo(x) => true,
+//                          _ => false,
+//                      };
+//                      if matches {
+//                          let from = from.clone();
+//                          *slot = None;
+//                          return Use(Arm0((from, msg)));
 //                      }
-//                      _ => (),
 //                  }
 //              }
-//              Skip(ReceivedMessage::Remote((from, m)))
-//          },
+//              [same for usize]
+//          }
 //      }
-//  })) {
-//      Arm1(msg) => match *msg {
-//          (_pid, (1, ref x)) => bar(x),
-//          _ => unreachable!(),
-//      },
-//      Arm2(msg) => match *msg {
-//          (_pid, (2, x)) => foobar(x),
-//          _ => unreachable!(),
-//      },
-//      Arm3(msg) => match *msg {
-//          (_pid, x) => quux(x),
-//          _ => unreachable!(),
+//      Skip
+//  }, Duration::from_secs(1)).await {
+//      Some(matched) => match matched {
+//          Arm0((from, msg)) => match (from, *msg) {
+//              (_pid, (1, ref x)) => bar(x),
+//              _ => unreachable!(),
+//          },
+//          Arm1((from, msg)) => match (from, *msg) {
+//              (_pid, x) => quux(x),
+//              _ => unreachable!(),
+//          },
 //      },
+//      None => timed_out(),
 //  }
+//
+// Without an `after` arm, `receive` is called instead of `receive_timeout`,
+// and its result is matched directly.
 
-// Note: the match guards will be evaluated in an `async move` closure, hence
-// it isn't possible to early-return from there, and every non-Copy local
-// variable used in guards will be moved. In exchange, it is possible to call
-// await!().
+// Note: the match guards are evaluated in an `async move` closure, hence it
+// isn't possible to early-return from there, and every non-Copy local variable
+// used in guards will be moved (borrowing them would make the future returned
+// by `receive!` not `Send`). In exchange, it is possible to `.await` in guards.
 pub fn receive(input: TokenStream) -> TokenStream {
     // TODO: (B) Give nicer parsing errors, pinpointing the error, etc.
     let parsed = syn::parse2::<Receive>(input).expect(
@@ -271,6 +256,7 @@ Reminder: syntax is as follows:
 receive! {
     (usize, String): (1, s) => foo(s),
     usize: x if bar(x) => { baz(x) }
+    after Duration::from_secs(1) => quux(),
 }
 ```
 ",
@@ -317,30 +303,41 @@ receive! {
         .map(|(i, arm)| gen_execute_match_arm(i, arm.pat, arm.body));
 
     // TODO: (A) assert for each type it's a Message
+    let handler = quote! {
+        async move |slot: &mut Option<::erlust::ReceivedMessage>| {
+            match slot.as_ref().unwrap() {
+                ::erlust::ReceivedMessage::Local((from, msg)) => {
+                    #(#local_matches)*
+                }
+                ::erlust::ReceivedMessage::Remote((from, m)) => {
+                    #(#remote_matches)*
+                }
+            }
+            ::erlust::ReceiveResult::Skip
+        }
+    };
+    let receive = match parsed.after {
+        None => quote! {
+            match ::erlust::receive(#handler).await {
+                #(#execute_match_arms)*
+            }
+        },
+        Some(AfterArm { timeout, body }) => quote! {
+            match ::erlust::receive_timeout(#handler, #timeout).await {
+                Some(matched) => match matched {
+                    #(#execute_match_arms)*
+                },
+                None => #body,
+            }
+        },
+    };
     let res = quote! {
         #[allow(unused_variables)]
         {
             #arms_def
 
-            match ::erlust::receive(async move |mut msg: ::erlust::ReceivedMessage| {
-                match msg {
-                    ::erlust::ReceivedMessage::Local((from, mut msg)) => {
-                        #(#local_matches)*
-                        ::erlust::ReceiveResult::Skip(
-                            ::erlust::ReceivedMessage::Local((from, msg))
-                        )
-                    }
-                    ::erlust::ReceivedMessage::Remote((from, m)) => {
-                        #(#remote_matches)*
-                        ::erlust::ReceiveResult::Skip(
-                            ::erlust::ReceivedMessage::Remote((from, m))
-                        )
-                    }
-                }
-            }).await {
-                #(#execute_match_arms)*
-            }
+            #receive
         }
     };
-    res.into()
+    res
 }
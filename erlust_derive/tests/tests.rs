@@ -1,11 +1,26 @@
-#![feature(stmt_expr_attributes, async_closure)]
+#![feature(stmt_expr_attributes)]
 
 #[macro_use]
 extern crate erlust_derive;
 #[macro_use]
 extern crate serde_derive;
 
+use erlust::{
+    global::{self, GlobalRegistry},
+    Accepts, Answers, Call, CallError, Caller, Chaos, ChaosTheater, ChildSpec, Down, Exit,
+    ExitReason, GenServer, GenServerPid, Handles, InjectError, LoopbackTheater, Next, Pid,
+    ReceiveResult, ReceivedMessage, Ref, StateEvent, StateMachine, Strategy, Supervisor,
+    TcpTheater, Transition, TypedPid,
+};
 use erlust_derive::receive;
+use futures::{
+    channel::oneshot,
+    executor::{LocalPool, ThreadPool},
+    future::{self, FutureObj},
+    task::{Spawn, SpawnError},
+    Future,
+};
+use std::{sync::mpsc, thread, time::Duration};
 
 #[derive(Deserialize, Message, Serialize)]
 #[erlust_tag = "foo"]
@@ -35,19 +50,40 @@ fn quux(x: usize) -> String {
     format!("{}", x)
 }
 
-// TODO: (B) make this example actually test both sending and receiving
+/// Runs the future built by `f` as an actor, and returns its result
+///
+/// `f` is given a spawner that can be used to spawn other actors.
+fn run_actor<F, Fut, T>(f: F) -> T
+where
+    F: FnOnce(ThreadPool) -> Fut,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let mut pool = ThreadPool::new().unwrap();
+    let (sender, receiver) = oneshot::channel();
+    let fut = f(pool.clone());
+    erlust::spawn_on(&mut pool, async move {
+        let _ = sender.send(fut.await);
+    })
+    .unwrap();
+    futures::executor::block_on(receiver).expect("actor terminated without answering")
+}
+
 #[test]
 fn passes_one_message() {
-    futures::executor::block_on(async {
-        assert_eq!(
-            "test",
-            receive! {
-                Foo: (_pid, Foo(1, x)) if foo(x) =>{ bar(x)},
-                Foo: (_pid, Foo(2, x)) => {foobar(x)},
-                Bar: (_pid, Bar(x)) if baz(*x) => {quux(x)},
-            }
-        );
+    let res = run_actor(|_| async {
+        let mut me = Pid::me();
+        me.send(Box::new(FooBar { hello: 0 })).await.unwrap();
+        me.send(Box::new(Foo(2, String::from("test"))))
+            .await
+            .unwrap();
+        receive! {
+            Foo: (_pid, Foo(1, x)) if foo(x) =>{ bar(x)},
+            Foo: (_pid, Foo(2, x)) => {foobar(x)},
+            Bar: (_pid, Bar(x)) if baz(*x) => {quux(x)},
+        }
     });
+    assert_eq!("test", res);
 }
 
 #[derive(Deserialize, Message, Serialize)]
@@ -55,3 +91,1207 @@ fn passes_one_message() {
 struct FooBar {
     hello: usize,
 }
+
+#[test]
+fn exit_propagates_along_links() {
+    let reason = run_actor(|pool| async move {
+        erlust::trap_exit(true);
+        let mut spawner = pool.clone();
+        erlust::spawn_link_on(&mut pool.clone(), async move {
+            erlust::spawn_link_on(&mut spawner, async { panic!("boom") }).unwrap();
+            receive! {
+                Bar: (_pid, _) => (),
+            }
+        })
+        .unwrap();
+        receive! {
+            Exit: (_pid, Exit { reason, .. }) => reason,
+        }
+    });
+    assert_eq!(ExitReason::Panic(String::from("boom")), reason);
+}
+
+#[test]
+fn trapped_exits_are_received() {
+    let reasons = run_actor(|mut pool| async move {
+        erlust::trap_exit(true);
+        erlust::spawn_link_on(&mut pool, async {}).unwrap();
+        let (pid, first) = receive! {
+            Exit: (_pid, Exit { pid, reason }) => (pid, reason),
+        };
+        // Linking to an already-terminated actor is an immediate exit
+        pid.link().await;
+        let second = receive! {
+            Exit: (_pid, Exit { reason, .. }) => reason,
+        };
+        (first, second)
+    });
+    assert_eq!((ExitReason::Normal, ExitReason::NoProc), reasons);
+}
+
+#[test]
+fn monitors_receive_down() {
+    let reasons = run_actor(|mut pool| async move {
+        let mut child = erlust::spawn_on(&mut pool, async {
+            receive! {
+                Bar: (_pid, _) => panic!("crash"),
+            }
+        })
+        .unwrap();
+        let monitor_ref = child.monitor().await;
+        child.send(Box::new(Bar(1))).await.unwrap();
+        let (r, first) = receive! {
+            Down: (_pid, Down { monitor_ref, reason, .. }) => (monitor_ref, reason),
+        };
+        assert_eq!(monitor_ref, r);
+        // Monitoring an already-terminated actor is an immediate down
+        child.monitor().await;
+        let second = receive! {
+            Down: (_pid, Down { reason, .. }) => reason,
+        };
+        (first, second)
+    });
+    assert_eq!(
+        (ExitReason::Panic(String::from("crash")), ExitReason::NoProc),
+        reasons
+    );
+}
+
+/// Child of a supervisor that tells `parent` it started, and then panics
+/// the first time it is started if `crash`
+fn reporting_child(parent: &Pid, name: &'static str, crash: bool) -> ChildSpec {
+    let parent = parent.clone();
+    let mut started = 0;
+    ChildSpec::new(move || {
+        let mut parent = parent.clone();
+        started += 1;
+        let n = started;
+        async move {
+            parent
+                .send(Box::new(Foo(n, String::from(name))))
+                .await
+                .unwrap();
+            if crash && n == 1 {
+                panic!("crashing {}", name);
+            }
+            receive! {
+                Bar: (_pid, _) => (),
+            }
+        }
+    })
+}
+
+#[test]
+fn supervisor_restarts_all_children() {
+    let started = run_actor(|pool| async move {
+        let me = Pid::me();
+        let sup = Supervisor::new(pool.clone(), Strategy::OneForAll)
+            .child(reporting_child(&me, "a", false))
+            .child(reporting_child(&me, "b", true));
+        erlust::spawn_on(&mut pool.clone(), sup.run()).unwrap();
+        let mut started = Vec::new();
+        for _ in 0..4 {
+            started.push(receive! {
+                Foo: (_pid, Foo(n, name)) => (name, n),
+            });
+        }
+        started
+    });
+    let expected = vec![("a", 1), ("b", 1), ("a", 2), ("b", 2)];
+    let expected = expected
+        .into_iter()
+        .map(|(name, n)| (String::from(name), n))
+        .collect::<Vec<_>>();
+    assert_eq!(expected, started);
+}
+
+#[test]
+fn supervisor_gives_up_after_too_many_restarts() {
+    let reason = run_actor(|pool| async move {
+        erlust::trap_exit(true);
+        let crashing = ChildSpec::new(|| async { panic!("always crashing") });
+        let sup = Supervisor::new(pool.clone(), Strategy::OneForOne)
+            .intensity(2, Duration::from_secs(60))
+            .child(crashing);
+        erlust::spawn_link_on(&mut pool.clone(), sup.run()).unwrap();
+        receive! {
+            Exit: (_pid, Exit { reason, .. }) => reason,
+        }
+    });
+    assert_eq!(ExitReason::Shutdown, reason);
+}
+
+#[test]
+fn supervisors_kill_children_that_ignore_shutdowns() {
+    let reasons = run_actor(|pool| async move {
+        erlust::trap_exit(true);
+        let me = Pid::me();
+        let stubborn = ChildSpec::new(move || {
+            let mut parent = me.clone();
+            async move {
+                erlust::trap_exit(true);
+                parent.send(Box::new(Ping(Pid::me(), 0))).await.unwrap();
+                future::pending::<()>().await;
+            }
+        })
+        .shutdown(Duration::from_millis(20));
+        let sup = Supervisor::new(pool.clone(), Strategy::OneForOne).child(stubborn);
+        let sup = erlust::spawn_link_on(&mut pool.clone(), sup.run()).unwrap();
+        let child = receive! {
+            Ping: (_pid, Ping(pid, _)) => pid,
+        };
+        child.monitor().await;
+        // The supervisor terminates with the reason of its parent
+        sup.exit(ExitReason::NoConnection).await;
+        let child_reason = receive! {
+            Down: (_pid, Down { reason, .. }) => reason,
+        };
+        let sup_reason = receive! {
+            Exit: (_pid, Exit { reason, .. }) => reason,
+        };
+        (child_reason, sup_reason)
+    });
+    assert_eq!((ExitReason::Killed, ExitReason::NoConnection), reasons);
+}
+
+/// A spawner that cannot spawn anything
+struct ShutDownSpawner;
+
+impl Spawn for ShutDownSpawner {
+    fn spawn_obj(&self, _: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+        Err(SpawnError::shutdown())
+    }
+}
+
+#[test]
+fn supervisor_terminates_when_children_cannot_start() {
+    let reason = run_actor(|mut pool| async move {
+        erlust::trap_exit(true);
+        let sup = Supervisor::new(ShutDownSpawner, Strategy::OneForOne)
+            .child(ChildSpec::new(|| async {}));
+        erlust::spawn_link_on(&mut pool, sup.run()).unwrap();
+        receive! {
+            Exit: (_pid, Exit { reason, .. }) => reason,
+        }
+    });
+    match reason {
+        ExitReason::StartFailed(_) => (),
+        reason => panic!("unexpected exit reason {:?}", reason),
+    }
+}
+
+#[test]
+fn registered_names_follow_actors() {
+    let res = run_actor(|mut pool| async move {
+        let me = Pid::me();
+        erlust::register("registry-test", &me).unwrap();
+        assert!(erlust::whereis("registry-test") == Some(me.clone()));
+        let taken = erlust::register("registry-test", &me);
+        assert_eq!(Err(erlust::RegistryError::NameTaken), taken);
+
+        erlust::spawn_on(&mut pool, async {
+            let child = Pid::me();
+            erlust::register("registry-child", &child).unwrap();
+            erlust::send_named("registry-test", Box::new(Bar(1)))
+                .await
+                .unwrap();
+        })
+        .unwrap();
+        let child = receive! {
+            Bar: (pid, Bar(1)) => pid,
+        };
+        // The child is unregistered once it terminates
+        child.monitor().await;
+        receive! {
+            Down: (_pid, _) => (),
+        }
+        let child_name = erlust::whereis("registry-child").is_some();
+        erlust::unregister("registry-test").unwrap();
+        (child_name, erlust::whereis("registry-test").is_some())
+    });
+    assert_eq!((false, false), res);
+}
+
+#[test]
+fn global_registry_without_peers() {
+    let res = run_actor(|mut pool| async move {
+        erlust::global::GlobalRegistry::new()
+            .spawn(&mut pool)
+            .unwrap();
+        let me = Pid::me();
+        erlust::global::register("global-test", &me).await.unwrap();
+        let taken = erlust::global::register("global-test", &me).await;
+        assert_eq!(Err(erlust::RegistryError::NameTaken), taken);
+        erlust::global::send("global-test", Box::new(Bar(3)))
+            .await
+            .unwrap();
+        let received = receive! {
+            Bar: (_pid, Bar(x)) => x,
+        };
+        let found = erlust::global::whereis("global-test").await == Some(me);
+        erlust::global::unregister("global-test").await.unwrap();
+        let gone = erlust::global::whereis("global-test").await.is_none();
+        (received, found, gone)
+    });
+    assert_eq!((3, true, true), res);
+}
+
+/// Waits for `duration`, keeping all the messages in the mailbox
+async fn sleep(duration: Duration) {
+    erlust::receive_timeout(
+        async move |_: &mut Option<ReceivedMessage>| ReceiveResult::Skip::<()>,
+        duration,
+    )
+    .await;
+}
+
+/// Waits for `registry` to bind `name` to an actor satisfying `expected`,
+/// and returns the last binding seen
+async fn wait_binding(
+    registry: &Pid,
+    name: &str,
+    expected: impl Fn(Option<&Pid>) -> bool,
+) -> Option<Pid> {
+    for _ in 0..100 {
+        let found = global::whereis_in(registry, name).await;
+        if expected(found.as_ref()) {
+            return found;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    global::whereis_in(registry, name).await
+}
+
+/// Spawns two registries, and returns them along with how each sees the
+/// other, in theaters `a` and `b`
+fn spawn_registries<F>(
+    pool: &mut ThreadPool,
+    a: &'static str,
+    b: &'static str,
+    resolver: F,
+) -> (Pid, Pid, Pid, Pid)
+where
+    F: 'static + Clone + Send + Sync + Fn(&str, &Pid, &Pid) -> Option<Pid>,
+{
+    let registry_a = GlobalRegistry::new()
+        .resolver(resolver.clone())
+        .spawn_unnamed(pool)
+        .unwrap();
+    let registry_b = GlobalRegistry::new()
+        .resolver(resolver)
+        .spawn_unnamed(pool)
+        .unwrap();
+    let b_from_a = Pid::remote(registry_b.actor_id(), LoopbackTheater::new(a, b));
+    let a_from_b = Pid::remote(registry_a.actor_id(), LoopbackTheater::new(b, a));
+    (registry_a, registry_b, b_from_a, a_from_b)
+}
+
+/// Spawns an actor that waits forever
+fn spawn_idle(pool: &mut ThreadPool) -> Pid {
+    erlust::spawn_on(pool, future::pending()).unwrap()
+}
+
+#[test]
+fn global_registries_replicate_to_peers() {
+    let res = run_actor(|mut pool| async move {
+        let (a, b, b_from_a, a_from_b) =
+            spawn_registries(&mut pool, "rep-a", "rep-b", global::unregister_both);
+        global::add_peer_in(&a, &b_from_a).await.unwrap();
+        global::add_peer_in(&b, &a_from_b).await.unwrap();
+        let x = spawn_idle(&mut pool);
+        global::register_in(&a, "x", &x).await.unwrap();
+        let x_from_b = Pid::remote(x.actor_id(), LoopbackTheater::new("rep-b", "rep-a"));
+        let added = wait_binding(&b, "x", |p| p == Some(&x_from_b)).await;
+        global::unregister_in(&a, "x").await.unwrap();
+        let removed = wait_binding(&b, "x", |p| p.is_none()).await;
+        (added == Some(x_from_b), removed.is_none())
+    });
+    assert_eq!((true, true), res);
+}
+
+#[test]
+fn global_registries_drop_names_of_disconnected_peers() {
+    let res = run_actor(|mut pool| async move {
+        let (a, b, b_from_a, a_from_b) =
+            spawn_registries(&mut pool, "drop-a", "drop-b", global::unregister_both);
+        global::add_peer_in(&a, &b_from_a).await.unwrap();
+        global::add_peer_in(&b, &a_from_b).await.unwrap();
+        let x = spawn_idle(&mut pool);
+        global::register_in(&a, "x", &x).await.unwrap();
+        let replicated = wait_binding(&b, "x", |p| p.is_some()).await.is_some();
+        LoopbackTheater::disconnect("drop-a", "drop-b");
+        let dropped = wait_binding(&b, "x", |p| p.is_none()).await.is_none();
+        // Names registered locally are kept
+        let kept = global::whereis_in(&a, "x").await == Some(x);
+        (replicated, dropped, kept)
+    });
+    assert_eq!((true, true, true), res);
+}
+
+#[test]
+fn global_registries_resolve_clashes_when_partitions_heal() {
+    let res = run_actor(|mut pool| async move {
+        // Keep the actor with the smallest id, which is the same whatever the
+        // theater it is seen from
+        let resolver = |_: &str, x: &Pid, y: &Pid| match x.actor_id() < y.actor_id() {
+            true => Some(x.clone()),
+            false => Some(y.clone()),
+        };
+        let (a, b, b_from_a, a_from_b) = spawn_registries(&mut pool, "heal-a", "heal-b", resolver);
+        global::add_peer_in(&a, &b_from_a).await.unwrap();
+        global::add_peer_in(&b, &a_from_b).await.unwrap();
+        // Each side registers a name, to know when the other side noticed
+        // the partition
+        let (sa, sb) = (spawn_idle(&mut pool), spawn_idle(&mut pool));
+        global::register_in(&a, "sa", &sa).await.unwrap();
+        global::register_in(&b, "sb", &sb).await.unwrap();
+        wait_binding(&b, "sa", |p| p.is_some()).await;
+        wait_binding(&a, "sb", |p| p.is_some()).await;
+        LoopbackTheater::disconnect("heal-a", "heal-b");
+        wait_binding(&b, "sa", |p| p.is_none()).await;
+        wait_binding(&a, "sb", |p| p.is_none()).await;
+        // Both sides of the partition register the same name
+        let (x, y) = (spawn_idle(&mut pool), spawn_idle(&mut pool));
+        global::register_in(&a, "c", &x).await.unwrap();
+        global::register_in(&b, "c", &y).await.unwrap();
+        LoopbackTheater::reconnect("heal-a", "heal-b");
+        global::add_peer_in(&a, &b_from_a).await.unwrap();
+        global::add_peer_in(&b, &a_from_b).await.unwrap();
+        let winner = x.actor_id().min(y.actor_id());
+        let is_winner = |p: Option<&Pid>| p.map(|p| p.actor_id()) == Some(winner);
+        let on_a = wait_binding(&a, "c", is_winner).await;
+        let on_b = wait_binding(&b, "c", is_winner).await;
+        (is_winner(on_a.as_ref()), is_winner(on_b.as_ref()))
+    });
+    assert_eq!((true, true), res);
+}
+
+#[test]
+fn global_registry_calls_fail_once_it_terminated() {
+    let res = run_actor(|mut pool| async move {
+        let registry = GlobalRegistry::new().spawn_unnamed(&mut pool).unwrap();
+        registry.exit(ExitReason::Kill).await;
+        global::register_in(&registry, "x", &Pid::me()).await
+    });
+    assert_eq!(Err(erlust::RegistryError::NotStarted), res);
+}
+
+#[test]
+fn global_registry_calls_time_out_when_messages_are_lost() {
+    let res = run_actor(|mut pool| async move {
+        let registry = GlobalRegistry::new().spawn_unnamed(&mut pool).unwrap();
+        let chaos = Chaos::new(0).drop_rate(1.);
+        let theater = ChaosTheater::new(LoopbackTheater::new("la", "lb"), chaos);
+        let registry = Pid::remote(registry.actor_id(), theater);
+        global::register_in(&registry, "x", &Pid::me()).await
+    });
+    assert_eq!(Err(erlust::RegistryError::Timeout), res);
+}
+
+#[test]
+fn receive_times_out_without_losing_messages() {
+    let res = run_actor(|_| async {
+        let mut me = Pid::me();
+        me.send(Box::new(Bar(4))).await.unwrap();
+        let timed_out = receive! {
+            Foo: (_pid, Foo(n, _)) => Some(n),
+            after Duration::from_millis(10) => None,
+        };
+        let kept = receive! {
+            Bar: (_pid, Bar(x)) => x,
+            after Duration::from_secs(0) => 0,
+        };
+        (timed_out, kept)
+    });
+    assert_eq!((None, 4), res);
+}
+
+#[test]
+fn interrupted_receive_keeps_messages() {
+    let res = run_actor(|_| async {
+        let mut me = Pid::me();
+        me.send(Box::new(Bar(5))).await.unwrap();
+        // Interrupted while waiting for a message
+        let waiting = Box::pin(async {
+            receive! {
+                Foo: (_pid, _) => (),
+            }
+        });
+        future::select(waiting, future::ready(())).await;
+        // Interrupted while handling a message
+        let handling = Box::pin(async {
+            receive! {
+                Bar: (_pid, _) if future::pending::<bool>().await => (),
+            }
+        });
+        future::select(handling, future::ready(())).await;
+        assert!(Pid::me() == me);
+        receive! {
+            Bar: (_pid, Bar(x)) => x,
+        }
+    });
+    assert_eq!(5, res);
+}
+
+#[test]
+fn sends_to_terminated_actors_fail() {
+    let res = run_actor(|mut pool| async move {
+        erlust::trap_exit(true);
+        let mut child = erlust::spawn_link_on(&mut pool, async {}).unwrap();
+        receive! {
+            Exit: (_pid, _) => (),
+        }
+        match child.send(Box::new(Bar(0))).await {
+            Err(e) => e.is_disconnected(),
+            Ok(()) => false,
+        }
+    });
+    assert!(res);
+}
+
+/// Answers calls with `Bar(x)` by `Bar(x + 1)`, late for `Bar(0)`, and
+/// crashes on `Bar(99)`
+async fn incrementer() {
+    loop {
+        let (from, x) = receive! {
+            Call<Bar>: (_pid, Call { from, request: Bar(x) }) => (from, x),
+        };
+        match x {
+            0 => sleep(Duration::from_millis(50)).await,
+            99 => panic!("crash"),
+            _ => (),
+        }
+        from.reply(Bar(x + 1)).await.unwrap();
+    }
+}
+
+#[test]
+fn calls_wait_for_their_reply() {
+    let res = run_actor(|mut pool| async move {
+        let server = erlust::spawn_on(&mut pool, incrementer()).unwrap();
+        let answered = server.call::<_, Bar>(Bar(1), Duration::from_secs(5)).await;
+        let late = server
+            .call::<_, Bar>(Bar(0), Duration::from_millis(10))
+            .await;
+        let next = server.call::<_, Bar>(Bar(3), Duration::from_secs(5)).await;
+        // The late reply was dropped instead of filling the mailbox
+        let leftover = erlust::receive_timeout(
+            async move |_: &mut Option<ReceivedMessage>| ReceiveResult::Use(()),
+            Duration::from_millis(10),
+        )
+        .await;
+        let down = server.call::<_, Bar>(Bar(99), Duration::from_secs(5)).await;
+        (
+            answered.unwrap().0,
+            matches!(late, Err(CallError::Timeout)),
+            next.unwrap().0,
+            leftover.is_none(),
+            match down {
+                Err(CallError::Down(ExitReason::Panic(msg))) => msg,
+                _ => String::new(),
+            },
+        )
+    });
+    assert_eq!((2, true, 4, true, String::from("crash")), res);
+}
+
+#[test]
+fn calls_put_back_what_they_skip_untouched() {
+    let res = run_actor(|mut pool| async move {
+        let server = erlust::spawn_on(&mut pool, incrementer()).unwrap();
+        // Receive a `Down` unrelated to the call from another theater
+        let mut me = Pid::remote(Pid::me().actor_id(), LoopbackTheater::new("ka", "kb"));
+        let down = Down {
+            monitor_ref: Ref::new(),
+            pid: server.clone(),
+            reason: ExitReason::Normal,
+        };
+        me.send(Box::new(down)).await.unwrap();
+        let answered = server.call::<_, Bar>(Bar(1), Duration::from_secs(5)).await;
+        let still_remote = erlust::receive(async |msg: &mut Option<ReceivedMessage>| {
+            ReceiveResult::Use(matches!(msg, Some(ReceivedMessage::Remote(_))))
+        })
+        .await;
+        (answered.unwrap().0, still_remote)
+    });
+    assert_eq!((2, true), res);
+}
+
+#[test]
+fn timed_out_calls_leave_nothing_behind() {
+    let leftover = run_actor(|mut pool| async move {
+        // A remote server that never answers, and crashes when told to
+        let server = erlust::spawn_on(&mut pool, async {
+            loop {
+                receive! {
+                    Call<Bar>: (_pid, _) => (),
+                    Bar: (_pid, _) => panic!("crash"),
+                }
+            }
+        })
+        .unwrap();
+        let mut server = Pid::remote(server.actor_id(), LoopbackTheater::new("ta", "tb"));
+        for i in 0..3 {
+            let res = server
+                .call::<_, Bar>(Bar(i), Duration::from_millis(10))
+                .await;
+            assert!(matches!(res, Err(CallError::Timeout)));
+        }
+        server.send(Box::new(Bar(0))).await.unwrap();
+        // Neither replies nor `Down`s of the calls reach the mailbox
+        erlust::receive_timeout(
+            async move |_: &mut Option<ReceivedMessage>| ReceiveResult::Use(()),
+            Duration::from_millis(50),
+        )
+        .await
+    });
+    assert!(leftover.is_none());
+}
+
+#[derive(Deserialize, Message, Serialize)]
+#[erlust_tag = "add"]
+struct Add(usize);
+
+/// Sums what it is cast, and tells `parent` the total when stopped by a
+/// `Foo` message, that it accepts along with the calls of its protocol
+///
+/// `Bar(0)` calls are answered after the next cast, and `parent` is told
+/// with a `Foo` message when one is waiting.
+struct Counter {
+    total:   usize,
+    parent:  Pid,
+    waiting: Option<Caller>,
+}
+
+impl GenServer for Counter {
+    type Call = Bar;
+    type Reply = Bar;
+    type Cast = Add;
+
+    async fn handle_call(&mut self, Bar(x): Bar, from: &Caller) -> (Option<Bar>, Next) {
+        if x == 0 {
+            self.waiting = Some(from.clone());
+            let waiting = Foo(0, String::from("waiting"));
+            self.parent.send(Box::new(waiting)).await.unwrap();
+            return (None, Next::Continue);
+        }
+        (Some(Bar(self.total + x)), Next::Continue)
+    }
+
+    async fn handle_cast(&mut self, Add(x): Add) -> Next {
+        self.total += x;
+        if let Some(caller) = self.waiting.take() {
+            caller.reply(Bar(self.total)).await.unwrap();
+        }
+        Next::Continue
+    }
+
+    async fn handle_info(&mut self, msg: ReceivedMessage) -> Next {
+        match msg {
+            ReceivedMessage::Local((_, m)) if m.as_any().is::<Foo>() => {
+                Next::Stop(ExitReason::Shutdown)
+            }
+            _ => Next::Continue,
+        }
+    }
+
+    async fn terminate(&mut self, _reason: &ExitReason) {
+        self.parent.send(Box::new(Bar(self.total))).await.unwrap();
+    }
+}
+
+impl Accepts<Foo> for Counter {}
+
+// Servers can also be protocols of their own, apart from the one of their
+// GenServerPid
+impl Answers<Add> for Counter {
+    type Reply = Add;
+}
+
+#[test]
+fn gen_servers_handle_calls_casts_and_infos() {
+    let res = run_actor(|mut pool| async move {
+        let mut parent = Pid::me();
+        let counter = Counter {
+            total:   0,
+            parent:  parent.clone(),
+            waiting: None,
+        };
+        let mut server = GenServerPid::spawn_on(&mut pool, counter).unwrap();
+        let monitor_ref = server.pid().monitor().await;
+        let waiter = server.clone();
+        erlust::spawn_on(&mut pool, async move {
+            let Bar(x) = waiter.call(Bar(0), Duration::from_secs(5)).await.unwrap();
+            parent.send(Box::new(Received(vec![x]))).await.unwrap();
+        })
+        .unwrap();
+        receive! {
+            Foo: (_pid, _) => (),
+        }
+        server.cast(Add(2)).await.unwrap();
+        server.cast(Add(3)).await.unwrap();
+        let called = server.call(Bar(1), Duration::from_secs(5)).await.unwrap().0;
+        let deferred = receive! {
+            Received: (_pid, Received(x)) => x,
+        };
+        server
+            .send(Box::new(Foo(0, String::from("stop"))))
+            .await
+            .unwrap();
+        let total = receive! {
+            Bar: (_pid, Bar(x)) => x,
+        };
+        let reason = receive! {
+            Down: (_pid, Down { monitor_ref: r, reason, .. }) if *r == monitor_ref => reason,
+        };
+        (deferred, called, total, reason)
+    });
+    assert_eq!((vec![2], 6, 5, ExitReason::Shutdown), res);
+}
+
+#[derive(PartialEq)]
+enum Lock {
+    Locked,
+    Open,
+}
+
+/// Opens on `Add(42)`, logs `Bar`s while open, and stops if left locked
+/// after a wrong code; the log is sent to `parent` when stopping
+struct CodeLock {
+    log:    Vec<usize>,
+    parent: Pid,
+}
+
+impl StateMachine for CodeLock {
+    type State = Lock;
+
+    async fn init(&mut self) -> Result<Lock, ExitReason> {
+        Ok(Lock::Locked)
+    }
+
+    fn handles(&self, state: &Lock) -> Handles {
+        match state {
+            Lock::Locked => Handles::new().message::<Add>(),
+            Lock::Open => Handles::new().message::<Bar>(),
+        }
+    }
+
+    async fn handle_event(&mut self, state: &Lock, event: StateEvent) -> Transition<Lock> {
+        match (state, event) {
+            (Lock::Locked, StateEvent::Message(msg)) => {
+                match *msg.downcast::<Add>().ok().unwrap().1 {
+                    Add(42) => Transition::to(Lock::Open).state_timeout(Duration::from_millis(20)),
+                    Add(x) => {
+                        self.log.push(x);
+                        Transition::keep().event_timeout(Duration::from_millis(20))
+                    }
+                }
+            }
+            (Lock::Open, StateEvent::Message(msg)) => {
+                let (_, bar) = msg.downcast::<Bar>().ok().unwrap();
+                self.log.push(bar.0);
+                Transition::keep()
+            }
+            (_, StateEvent::StateTimeout) => {
+                self.log.push(100);
+                Transition::to(Lock::Locked)
+            }
+            (_, StateEvent::EventTimeout) => {
+                self.log.push(200);
+                Transition::stop(ExitReason::Normal)
+            }
+        }
+    }
+
+    async fn terminate(&mut self, _state: &Lock, _reason: &ExitReason) {
+        let log = std::mem::take(&mut self.log);
+        self.parent.send(Box::new(Received(log))).await.unwrap();
+    }
+}
+
+#[test]
+fn state_machines_postpone_messages() {
+    let log = run_actor(|mut pool| async move {
+        let parent = Pid::me();
+        let machine = CodeLock {
+            log: Vec::new(),
+            parent,
+        };
+        let mut pid = erlust::spawn_on(&mut pool, erlust::run_state_machine(machine)).unwrap();
+        pid.send(Box::new(Bar(1))).await.unwrap();
+        pid.send(Box::new(Add(7))).await.unwrap();
+        pid.send(Box::new(Add(42))).await.unwrap();
+        pid.send(Box::new(Add(5))).await.unwrap();
+        receive! {
+            Received: (_pid, Received(log)) => log,
+        }
+    });
+    assert_eq!(vec![7, 1, 100, 5, 200], log);
+}
+
+/// The protocol of [`incrementer`]
+enum Incrementer {}
+
+impl Answers<Bar> for Incrementer {
+    type Reply = Bar;
+}
+
+#[derive(Deserialize, Message, Serialize)]
+#[erlust_tag = "serve"]
+struct Serve(TypedPid<Incrementer>);
+
+#[test]
+fn typed_pids_carry_their_protocol() {
+    let res = run_actor(|mut pool| async move {
+        let server = erlust::spawn_on(&mut pool, incrementer()).unwrap();
+        // Pretend the server lives in theater "tb", and have the typed pid
+        // travel back to us from there
+        let remote = Pid::remote(server.actor_id(), LoopbackTheater::new("ta", "tb"));
+        let mut me = Pid::remote(Pid::me().actor_id(), LoopbackTheater::new("tb", "ta"));
+        me.send(Box::new(Serve(TypedPid::from_pid(remote.clone()))))
+            .await
+            .unwrap();
+        let server = receive! {
+            Serve: (_pid, Serve(server)) => server,
+        };
+        let answer = server.call(Bar(6), Duration::from_secs(5)).await;
+        (Pid::from(server) == remote, answer.unwrap().0)
+    });
+    assert_eq!((true, 7), res);
+}
+
+#[test]
+fn spawns_on_the_default_executor() {
+    erlust::set_executor(ThreadPool::new().unwrap());
+    let res = run_actor(|_| async {
+        let mut me = Pid::me();
+        erlust::spawn(async move {
+            erlust::spawn_link(async {}).unwrap();
+            me.send(Box::new(Bar(6))).await.unwrap();
+        })
+        .unwrap();
+        receive! {
+            Bar: (_pid, Bar(x)) => x,
+        }
+    });
+    assert_eq!(6, res);
+}
+
+#[test]
+fn spawn_options_are_applied() {
+    let res = run_actor(|mut pool| async move {
+        let me = Pid::me();
+        let (_child, monitor_ref) = erlust::SpawnOptions::new()
+            .mailbox_capacity(4)
+            .waiting_limit(1, erlust::Overflow::DropOldest)
+            .name("options-test")
+            .spawn_monitor_on(&mut pool, async move {
+                let mut parent = me;
+                receive! {
+                    Foo: (_pid, _) => (),
+                }
+                let x = receive! {
+                    Bar: (_pid, Bar(x)) => x,
+                };
+                parent.send(Box::new(Bar(x))).await.unwrap();
+            })
+            .unwrap();
+        let mut child = erlust::whereis("options-test").unwrap();
+        child.send(Box::new(Bar(1))).await.unwrap();
+        child.send(Box::new(Bar(2))).await.unwrap();
+        child.send(Box::new(Foo(0, String::new()))).await.unwrap();
+        let x = receive! {
+            Bar: (_pid, Bar(x)) => x,
+        };
+        let r = receive! {
+            Down: (_pid, Down { monitor_ref, .. }) => monitor_ref,
+        };
+        (x, r == monitor_ref)
+    });
+    assert_eq!((2, true), res);
+}
+
+#[test]
+fn full_waiting_queues_drop_newest_messages() {
+    let res = run_actor(|mut pool| async move {
+        let me = Pid::me();
+        let mut child = erlust::SpawnOptions::new()
+            .waiting_limit(1, erlust::Overflow::DropNewest)
+            .spawn_on(&mut pool, async move {
+                let mut parent = me;
+                receive! {
+                    Foo: (_pid, _) => (),
+                }
+                loop {
+                    let x = receive! {
+                        Bar: (_pid, Bar(x)) => Some(x),
+                        after Duration::from_millis(0) => None,
+                    };
+                    match x {
+                        Some(x) => parent.send(Box::new(Bar(x))).await.unwrap(),
+                        None => break,
+                    }
+                }
+                parent.send(Box::new(Foo(0, String::new()))).await.unwrap();
+            })
+            .unwrap();
+        child.send(Box::new(Bar(1))).await.unwrap();
+        child.send(Box::new(Bar(2))).await.unwrap();
+        child.send(Box::new(Foo(0, String::new()))).await.unwrap();
+        let mut received = Vec::new();
+        loop {
+            let x = receive! {
+                Bar: (_pid, Bar(x)) => Some(x),
+                Foo: (_pid, _) => None,
+            };
+            match x {
+                Some(x) => received.push(x),
+                None => break received,
+            }
+        }
+    });
+    assert_eq!(vec![1], res);
+}
+
+#[test]
+fn full_waiting_queues_kill_actors_right_away() {
+    let res = run_actor(|mut pool| async move {
+        let me = Pid::me();
+        let (mut child, _) = erlust::SpawnOptions::new()
+            .waiting_limit(1, erlust::Overflow::Kill)
+            .spawn_monitor_on(&mut pool, async move {
+                let mut parent = me;
+                receive! {
+                    Foo: (_pid, _) => (),
+                }
+                parent.send(Box::new(Foo(0, String::new()))).await.unwrap();
+            })
+            .unwrap();
+        child.send(Box::new(Bar(1))).await.unwrap();
+        child.send(Box::new(Bar(2))).await.unwrap();
+        child.send(Box::new(Foo(0, String::new()))).await.unwrap();
+        receive! {
+            Foo: (_pid, _) => None,
+            Down: (_pid, Down { reason, .. }) => Some(reason),
+        }
+    });
+    assert_eq!(Some(ExitReason::Overflow), res);
+}
+
+#[derive(Deserialize, Message, Serialize)]
+#[erlust_tag = "ping"]
+struct Ping(Pid, usize);
+
+#[test]
+fn loopback_theater_passes_pids() {
+    let res = run_actor(|mut pool| async move {
+        let mut root = Pid::me();
+        // Pretend `root`, `relay` and `target` live in theaters "pa", "pb" and
+        // "pc", so that `target` reaches `relay` through "pc"
+        let relay = erlust::spawn_on(&mut pool, async {
+            let (pid, x) = receive! {
+                Ping: (_pid, Ping(pid, x)) => (pid, x),
+            };
+            let mut target = pid;
+            target.send(Box::new(Bar(x + 1))).await.unwrap();
+        })
+        .unwrap();
+        let relay_id = relay.actor_id();
+        let target = erlust::spawn_on(&mut pool, async move {
+            let (from, x) = receive! {
+                Bar: (pid, Bar(x)) => (pid, x),
+            };
+            let expected = Pid::remote(relay_id, LoopbackTheater::new("pc", "pb"));
+            root.send(Box::new(Foo(x, format!("{}", from == expected))))
+                .await
+                .unwrap();
+        })
+        .unwrap();
+        let mut relay = Pid::remote(relay.actor_id(), LoopbackTheater::new("pa", "pb"));
+        let target = Pid::remote(target.actor_id(), LoopbackTheater::new("pa", "pc"));
+        relay.send(Box::new(Ping(target, 41))).await.unwrap();
+        receive! {
+            Foo: (_pid, Foo(x, from_relay)) => (x, from_relay),
+        }
+    });
+    assert_eq!((42, String::from("true")), res);
+}
+
+#[test]
+fn loopback_theater_rejects_pids_of_other_theaters() {
+    let res = run_actor(|_pool| async move {
+        let me = Pid::me().actor_id();
+        let mut remote = Pid::remote(me, LoopbackTheater::new("fa", "fb"));
+        let foreign = Pid::remote(me, TcpTheater::new(([127, 0, 0, 1], 1).into()));
+        matches!(
+            remote.send(Box::new(Ping(foreign, 0))).await,
+            Err(erlust::Error::Serialization(_))
+        )
+    });
+    assert!(res);
+}
+
+#[test]
+fn loopback_theater_carries_signals() {
+    let reasons = run_actor(|mut pool| async move {
+        erlust::trap_exit(true);
+        let child = erlust::spawn_on(&mut pool, async {
+            receive! {
+                Bar: (_pid, _) => (),
+            }
+        })
+        .unwrap();
+        let mut child = Pid::remote(child.actor_id(), LoopbackTheater::new("sa", "sb"));
+        child.link().await;
+        let monitor_ref = child.monitor().await;
+        child.send(Box::new(Bar(0))).await.unwrap();
+        let down = receive! {
+            Down: (_pid, Down { monitor_ref: r, pid, reason }) => {
+                assert!(r == monitor_ref && pid == child);
+                reason
+            },
+        };
+        let exit = receive! {
+            Exit: (_pid, Exit { reason, .. }) => reason,
+        };
+        // The actor no longer exists
+        child.monitor().await;
+        let noproc = receive! {
+            Down: (_pid, Down { reason, .. }) => reason,
+        };
+        // The connection to the theater of the actor is lost
+        let other = erlust::spawn_on(&mut pool, async {
+            receive! {
+                Bar: (_pid, _) => (),
+            }
+        })
+        .unwrap();
+        let other = Pid::remote(other.actor_id(), LoopbackTheater::new("sa", "sb"));
+        other.link().await;
+        LoopbackTheater::disconnect("sa", "sb");
+        let noconnection = receive! {
+            Exit: (_pid, Exit { pid, reason }) if *pid == other => reason,
+        };
+        (down, exit, noproc, noconnection)
+    });
+    assert_eq!(
+        (
+            ExitReason::Normal,
+            ExitReason::Normal,
+            ExitReason::NoProc,
+            ExitReason::NoConnection
+        ),
+        reasons
+    );
+}
+
+#[test]
+fn dropped_actors_notify_remote_actors() {
+    let reasons = run_actor(|_| async {
+        erlust::trap_exit(true);
+        let (pid_sender, pid_receiver) = oneshot::channel();
+        let (run_sender, run_receiver) = mpsc::channel();
+        // Run the child on an executor that is dropped while the child is
+        // still waiting
+        thread::spawn(move || {
+            let mut pool = LocalPool::new();
+            let child = erlust::spawn_on(&mut pool.spawner(), future::pending()).unwrap();
+            pid_sender.send(child).unwrap_or(());
+            run_receiver.recv().unwrap();
+            pool.run_until_stalled();
+        });
+        let child = pid_receiver.await.unwrap();
+        let child = Pid::remote(child.actor_id(), LoopbackTheater::new("ra", "rb"));
+        child.link().await;
+        child.monitor().await;
+        run_sender.send(()).unwrap();
+        let exit = receive! {
+            Exit: (_pid, Exit { reason, .. }) => reason,
+        };
+        let down = receive! {
+            Down: (_pid, Down { reason, .. }) => reason,
+        };
+        (exit, down)
+    });
+    assert_eq!((ExitReason::Dropped, ExitReason::Dropped), reasons);
+}
+
+#[test]
+fn sends_to_terminated_remote_actors_fail() {
+    let res = run_actor(|mut pool| async move {
+        let (sender, receiver) = oneshot::channel();
+        let child = erlust::spawn_on(&mut pool, async {
+            let _ = sender.send(());
+        })
+        .unwrap();
+        let _ = receiver.await;
+        let mut child = Pid::remote(child.actor_id(), LoopbackTheater::new("ia", "ib"));
+        // Wait for the actor to be gone
+        child.monitor().await;
+        receive! {
+            Down: (_pid, _) => (),
+        }
+        match child.send(Box::new(Bar(0))).await {
+            Err(erlust::Error::Inject(e)) => e == erlust::InjectError::NoSuchActor,
+            _ => false,
+        }
+    });
+    assert!(res);
+}
+
+#[test]
+fn inject_reports_undeliverable_messages() {
+    let res = run_actor(|mut pool| async move {
+        let actor = erlust::SpawnOptions::new()
+            .mailbox_capacity(1)
+            .spawn_on(&mut pool, future::pending())
+            .unwrap();
+        let inject = |to| {
+            let theater = Box::new(LoopbackTheater::new("ja", "jb"));
+            erlust::inject(0, to, String::from("bar"), b"[0]".to_vec(), theater)
+        };
+        let missing = inject(actor.actor_id().wrapping_add(1)).await;
+        let mut delivered = 0;
+        let full = loop {
+            match inject(actor.actor_id()).await {
+                Ok(()) => delivered += 1,
+                Err(e) => break e,
+            }
+        };
+        (missing, delivered > 0, full)
+    });
+    assert_eq!(
+        (
+            Err(InjectError::NoSuchActor),
+            true,
+            InjectError::MailboxFull
+        ),
+        res
+    );
+}
+
+#[test]
+fn guessed_actor_ids_are_rejected() {
+    let res = run_actor(|mut pool| async move {
+        let actor = erlust::spawn_on(&mut pool, async {
+            receive! {
+                Bar: (_pid, _) => (),
+            }
+        })
+        .unwrap();
+        let mut rejected = 0;
+        for guess in (0..100).chain(Some(actor.actor_id().wrapping_add(1))) {
+            let mut pid = Pid::remote(guess, LoopbackTheater::new("ga", "gb"));
+            if let Err(erlust::Error::Inject(erlust::InjectError::NoSuchActor)) =
+                pid.send(Box::new(Bar(0))).await
+            {
+                rejected += 1;
+            }
+        }
+        actor.clone().send(Box::new(Bar(0))).await.unwrap();
+        rejected
+    });
+    assert_eq!(101, res);
+}
+
+#[derive(Deserialize, Message, Serialize)]
+#[erlust_tag = "received"]
+struct Received(Vec<usize>);
+
+/// Sends 0..20 through a [`ChaosTheater`] with policy `chaos`, and returns
+/// what was received
+fn send_through_chaos(chaos: Chaos) -> Vec<usize> {
+    run_actor(|mut pool| async move {
+        let mut me = Pid::me();
+        let mut collector = erlust::spawn_on(&mut pool, async move {
+            let mut received = Vec::new();
+            while let Some(x) = receive! {
+                Bar: (_pid, Bar(x)) => Some(x),
+                Foo: (_pid, _) => None,
+            } {
+                received.push(x);
+            }
+            me.send(Box::new(Received(received))).await.unwrap();
+        })
+        .unwrap();
+        let theater = ChaosTheater::new(LoopbackTheater::new("ca", "cb"), chaos);
+        let mut remote = Pid::remote(collector.actor_id(), theater);
+        for i in 0..20 {
+            remote.send(Box::new(Bar(i))).await.unwrap();
+        }
+        collector
+            .send(Box::new(Foo(0, String::new())))
+            .await
+            .unwrap();
+        receive! {
+            Received: (_pid, Received(received)) => received,
+        }
+    })
+}
+
+#[test]
+fn chaos_theater_is_deterministic() {
+    let chaos = || {
+        Chaos::new(42)
+            .drop_rate(0.2)
+            .duplicate_rate(0.2)
+            .reorder_rate(0.2)
+            .max_delay(Duration::from_millis(1))
+    };
+    let first = send_through_chaos(chaos());
+    assert_ne!((0..20).collect::<Vec<_>>(), first);
+    assert_eq!(first, send_through_chaos(chaos()));
+    assert_eq!(
+        (0..20).collect::<Vec<_>>(),
+        send_through_chaos(Chaos::new(42))
+    );
+}
+
+#[test]
+fn chaos_theater_sends_held_messages_eventually() {
+    let res = run_actor(|mut pool| async move {
+        let mut me = Pid::me();
+        let echo = erlust::spawn_on(&mut pool, async move {
+            let x = receive! {
+                Bar: (_pid, Bar(x)) => x,
+            };
+            me.send(Box::new(Bar(x))).await.unwrap();
+        })
+        .unwrap();
+        let chaos = Chaos::new(0).reorder_rate(1.);
+        let theater = ChaosTheater::new(LoopbackTheater::new("ha", "hb"), chaos);
+        let mut remote = Pid::remote(echo.actor_id(), theater);
+        remote.send(Box::new(Bar(7))).await.unwrap();
+        receive! {
+            Bar: (_pid, Bar(x)) => Some(x),
+            after Duration::from_secs(5) => None,
+        }
+    });
+    assert_eq!(Some(7), res);
+}
+
+#[test]
+fn chaos_theater_partitions_theaters() {
+    let res = run_actor(|mut pool| async move {
+        erlust::trap_exit(true);
+        let child = erlust::spawn_on(&mut pool, async {
+            receive! {
+                Bar: (_pid, _) => (),
+            }
+        })
+        .unwrap();
+        let chaos = Chaos::new(0);
+        let theater = ChaosTheater::new(LoopbackTheater::new("da", "db"), chaos.clone());
+        let mut child = Pid::remote(child.actor_id(), theater);
+        child.link().await;
+        chaos.partition_one_way(vec![LoopbackTheater::new("da", "db")]);
+        let reason = receive! {
+            Exit: (_pid, Exit { reason, .. }) => reason,
+        };
+        let partitioned = child.send(Box::new(Bar(0))).await.is_err();
+        chaos.heal();
+        let healed = child.send(Box::new(Bar(0))).await.is_ok();
+        (reason, partitioned, healed)
+    });
+    assert_eq!((ExitReason::NoConnection, true, true), res);
+}
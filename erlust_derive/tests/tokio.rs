@@ -0,0 +1,200 @@
+#![feature(stmt_expr_attributes)]
+
+// These tests run on tokio runtimes that are shut down when they return,
+// possibly after setting them as the default executor, so they live apart
+// from the other tests.
+
+#[macro_use]
+extern crate erlust_derive;
+#[macro_use]
+extern crate serde_derive;
+
+use erlust::{
+    rustls::{
+        pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+        RootCertStore,
+    },
+    Pid, TcpTheater, TlsTheater, TokioSpawner, UnixTheater,
+};
+use erlust_derive::receive;
+use futures::channel::oneshot;
+use std::{
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[derive(Deserialize, Message, Serialize)]
+#[erlust_tag = "baz"]
+struct Baz(usize);
+
+#[erlust::main]
+async fn root() -> (Option<usize>, Option<usize>) {
+    let mut me = Pid::me();
+    erlust::spawn_blocking(async move {
+        let x = (1..=10).sum();
+        me.send(Box::new(Baz(x))).await.unwrap();
+    })
+    .unwrap();
+    let first = receive! {
+        Baz: (_pid, Baz(x)) => Some(x),
+        after Duration::from_secs(10) => None,
+    };
+    let second = receive! {
+        Baz: (_pid, Baz(x)) => Some(x),
+        after Duration::from_millis(10) => None,
+    };
+    (first, second)
+}
+
+#[test]
+fn main_runs_the_root_actor() {
+    // The blocking actor sends its sum once
+    let sum = (1..=10).sum();
+    assert_eq!((Some(sum), None), root());
+}
+
+/// Opens a connection to a stream theater, pretending to be the theater
+/// described by `announced`, and returns whether the connection is accepted
+async fn accepted_as<S>(mut stream: S, announced: &[u8]) -> bool
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_u8(0).await.unwrap();
+    stream.write_u32(announced.len() as u32).await.unwrap();
+    stream.write_all(announced).await.unwrap();
+    // The announced theater never received this challenge, so it will not
+    // confirm it when verified
+    stream.read_u128().await.unwrap();
+    stream.write_u8(1).await.unwrap();
+    matches!(stream.read_u8().await, Ok(1))
+}
+
+#[test]
+fn tcp_theater_delivers_messages() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let res = runtime.block_on(async {
+        let theater = TcpTheater::listen("127.0.0.1:0").await.unwrap();
+        let mut spawner = TokioSpawner::current();
+        let echo = erlust::spawn_on(&mut spawner, async {
+            let (mut pid, x) = receive! {
+                Baz: (pid, Baz(x)) => (pid, x),
+            };
+            pid.send(Box::new(Baz(x + 1))).await.unwrap();
+        })
+        .unwrap();
+        // Pretend to be the local theater, from another connection
+        let stream = tokio::net::TcpStream::connect(theater.addr())
+            .await
+            .unwrap();
+        let port = theater.addr().port().to_be_bytes();
+        let impostor_accepted = accepted_as(stream, &port).await;
+        // Go through TCP even though the actor is local
+        let mut remote_echo = Pid::remote(echo.actor_id(), theater.clone());
+        let mut nobody = Pid::remote(0, theater);
+        let (sender, receiver) = oneshot::channel();
+        erlust::spawn_on(&mut spawner, async move {
+            // Records are injected in order, so this one is dropped before
+            // the echo answers
+            nobody.send(Box::new(Baz(0))).await.unwrap();
+            remote_echo.send(Box::new(Baz(41))).await.unwrap();
+            let x = receive! {
+                Baz: (_pid, Baz(x)) => x,
+            };
+            sender.send(x).unwrap();
+        })
+        .unwrap();
+        let x = receiver.await.unwrap();
+        (impostor_accepted, x, TcpTheater::undelivered())
+    });
+    assert_eq!((false, 42, 1), res);
+}
+
+#[test]
+fn unix_theater_delivers_messages() {
+    let path = std::env::temp_dir().join(format!("erlust-test-{}.sock", std::process::id()));
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let res = runtime.block_on(async {
+        let theater = UnixTheater::listen(&path).unwrap();
+        let mut spawner = TokioSpawner::current();
+        let echo = erlust::spawn_on(&mut spawner, async {
+            let (mut pid, x) = receive! {
+                Baz: (pid, Baz(x)) => (pid, x),
+            };
+            pid.send(Box::new(Baz(x + 1))).await.unwrap();
+        })
+        .unwrap();
+        // Pretend to be the local theater, from another connection run by
+        // the same user
+        let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let announced = theater.path().as_os_str().as_bytes();
+        let impostor_accepted = accepted_as(stream, announced).await;
+        // Go through the socket even though the actor is local, without
+        // knowing its credentials
+        let mut remote_echo = Pid::remote(echo.actor_id(), UnixTheater::new(&path).unwrap());
+        // Replies come from the theater with the credentials of this process
+        let echo = Pid::remote(echo.actor_id(), theater.clone());
+        let (sender, receiver) = oneshot::channel();
+        erlust::spawn_on(&mut spawner, async move {
+            remote_echo.send(Box::new(Baz(22))).await.unwrap();
+            let (from, x) = receive! {
+                Baz: (pid, Baz(x)) => (pid, x),
+            };
+            sender.send((from == echo, from == remote_echo, x)).unwrap();
+        })
+        .unwrap();
+        let uid = std::fs::metadata(&path).unwrap().uid();
+        let credentials = theater.credentials().map(|(u, _)| u == uid);
+        (impostor_accepted, credentials, receiver.await.unwrap())
+    });
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((false, Some(true), (true, false, 23)), res);
+}
+
+#[test]
+fn tls_theater_authenticates_theaters() {
+    let key = rcgen::KeyPair::generate().unwrap();
+    let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "theater-a");
+    let cert = params.self_signed(&key).unwrap();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.der().clone()).unwrap();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let res = runtime.block_on(async move {
+        let theater = TlsTheater::listen("127.0.0.1:0", vec![cert.der().clone()], key, roots)
+            .await
+            .unwrap();
+        assert_eq!("CN=theater-a", theater.subject());
+        let mut spawner = TokioSpawner::current();
+        let echo = erlust::spawn_on(&mut spawner, async {
+            let (mut pid, x) = receive! {
+                Baz: (pid, Baz(x)) => (pid, x),
+            };
+            pid.send(Box::new(Baz(x + 1))).await.unwrap();
+        })
+        .unwrap();
+        // The theater found at this address does not have this subject
+        let mut impostor = Pid::remote(
+            echo.actor_id(),
+            TlsTheater::new("CN=theater-b", theater.addr()),
+        );
+        // Go through TLS even though the actor is local
+        let mut remote_echo = Pid::remote(echo.actor_id(), theater);
+        let (sender, receiver) = oneshot::channel();
+        erlust::spawn_on(&mut spawner, async move {
+            let rejected = impostor.send(Box::new(Baz(0))).await.is_err();
+            remote_echo.send(Box::new(Baz(9))).await.unwrap();
+            let x = receive! {
+                Baz: (_pid, Baz(x)) => x,
+            };
+            sender.send((rejected, x)).unwrap();
+        })
+        .unwrap();
+        receiver.await.unwrap()
+    });
+    assert_eq!((true, 10), res);
+}